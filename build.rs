@@ -16,6 +16,10 @@ const PLATFORM: &str = "macos";
 #[cfg(target_os = "windows")]
 const PLATFORM: &str = "win64";
 
+// ResourceLimits in src/limits.rs mirrors TBuiltInResource at this release,
+// spirv.cpp fails to build if the layout changes
+const GLSLANG_REVISION: &str = "8.13.3743";
+
 struct Config<'a> {
     root: &'a str,
     build_dir: &'a Path,
@@ -76,7 +80,9 @@ fn main() {
             .flag_if_supported("/EHsc")     
             .file("src/spirv.cpp")
             .file("src/book.cpp")
+            .file("src/cross.cpp")
             .file("src/keywords.cpp")
+            .file("src/reflect.cpp")
             .compile("spirvwrapper");
     } else {
         cc::Build::new()
@@ -87,7 +93,9 @@ fn main() {
             .flag_if_supported("-fPIC")
             .file("src/spirv.cpp")
             .file("src/book.cpp")
+            .file("src/cross.cpp")
             .file("src/keywords.cpp")
+            .file("src/reflect.cpp")
             .compile("spirvwrapper");
     }
 
@@ -121,6 +129,7 @@ fn main() {
     println!("cargo:rustc-link-lib=static=spirv-cross-glsl");
     println!("cargo:rustc-link-lib=static=spirv-cross-hlsl");
     println!("cargo:rustc-link-lib=static=spirv-cross-msl");
+    println!("cargo:rustc-link-lib=static=spirv-cross-reflect");
     println!("cargo:rustc-link-lib=static=glfw3");
     println!("cargo:rustc-link-lib=static=freetype");
     //println!("cargo:rustc-link-lib=static=SPVRemapper");
@@ -137,6 +146,16 @@ fn clone_repository(name: &str, path: &Path) {
         .expect("Can't spawn Git!!");
 }
 
+fn checkout_revision(path: &Path, revision: &str) {
+    Command::new("git")
+        .arg("-C")
+        .arg(path)
+        .arg("checkout")
+        .arg(revision)
+        .output()
+        .expect("Can't spawn Git!!");
+}
+
 fn create_if_not_exist(path: &Path) {
     match fs::create_dir_all(path) {
         Ok(_) => {}
@@ -207,6 +226,7 @@ fn build_glslang(config: &Config) {
         Err(_) => {
             let glslang = Path::new(config.root).join("glslang");
             clone_repository("git@github.com:KhronosGroup/glslang.git", &glslang);
+            checkout_revision(&glslang, GLSLANG_REVISION);
             let tools = glslang.join("External").join("spirv-tools");
             clone_repository("git@github.com:KhronosGroup/SPIRV-Tools.git", &tools);
             let headers = tools.join("external").join("spirv-headers");
@@ -269,7 +289,7 @@ fn build_spirv_cross(config: &Config) {
     match fs::metadata(
         config
             .lib_dir
-            .join(get_platform_lib_name("spirv-cross-core")),
+            .join(get_platform_lib_name("spirv-cross-reflect")),
     ) {
         Err(_) => {
             let spirv_cross = Path::new(config.root).join("spirv-cross");
@@ -285,6 +305,7 @@ fn build_spirv_cross(config: &Config) {
                     "spirv-cross-glsl",
                     "spirv-cross-msl",
                     "spirv-cross-hlsl",
+                    "spirv-cross-reflect",
                 ],
                 &config,
             );
@@ -69,11 +69,11 @@ bool Book::AddChapter(const std::vector<uint32_t>& c) {
   return true;
 }
 
-extern "C" void CreateBook() {
-  book.reset(new Book(spirv));
+extern "C" void CreateBook(unsigned int* ptr, std::size_t length) {
+  book.reset(new Book(std::vector<uint32_t>(ptr, ptr + length)));
 }
 
-extern "C" bool AddChapter() {
-  book->AddChapter(spirv);
+extern "C" bool AddChapter(unsigned int* ptr, std::size_t length) {
+  book->AddChapter(std::vector<uint32_t>(ptr, ptr + length));
   return true;
 }
\ No newline at end of file
@@ -0,0 +1,317 @@
+use crate::collection::VariantCollection;
+use crate::keywords::KeywordSpace;
+use crate::limits::ResourceLimits;
+use crate::lint::KeywordUsage;
+use crate::module::SpirvModule;
+use crate::options::CompileOptions;
+use crate::preamble::PreambleScope;
+use crate::variant::VariantKey;
+use crate::{ShaderCompiler, Stage};
+use std::thread;
+
+/// The compiled stages of one keyword permutation.
+pub struct VariantResult {
+    /// `KeywordSpace::index` of the permutation
+    pub index: usize,
+    /// Entry picked on each axis of the keyword space
+    pub indices: Vec<usize>,
+    /// Keywords of the permutation, in axis order
+    pub keywords: Vec<String>,
+    /// Sorted keywords the variant was compiled with, the enabled ones too
+    pub all_keywords: Vec<String>,
+    /// `VariantKey::new` of `all_keywords`
+    pub key: VariantKey,
+    pub linked: bool,
+    pub stages: Vec<(Stage, SpirvModule)>,
+}
+
+/// Compiles every permutation of a `KeywordSpace` across worker threads. Each
+/// worker owns its own `ShaderCompiler`, and so its own thread-local glslang
+/// state, while global keywords are shared through the registry's mutexes.
+/// Keywords enabled on the thread that calls `build`, directly or through a
+/// `KeywordScope`, are enabled on every worker too.
+pub struct VariantBuilder {
+    stages: Vec<(Stage, String)>,
+    threads: usize,
+    limits: ResourceLimits,
+    options: CompileOptions,
+    preamble: Option<String>,
+    include_dirs: Vec<String>,
+    virtual_includes: Vec<(String, String)>,
+}
+
+impl VariantBuilder {
+    pub fn new() -> Self {
+        VariantBuilder {
+            stages: Vec::new(),
+            threads: thread::available_parallelism()
+                .map(|n| n.get())
+                .unwrap_or(1),
+            limits: ResourceLimits::default(),
+            options: CompileOptions::default(),
+            preamble: None,
+            include_dirs: Vec::new(),
+            virtual_includes: Vec::new(),
+        }
+    }
+
+    pub fn stage(mut self, stage: Stage, source: &str) -> Self {
+        self.stages.push((stage, source.to_string()));
+        self
+    }
+
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = threads.max(1);
+        self
+    }
+
+    pub fn limits(mut self, limits: &ResourceLimits) -> Self {
+        self.limits = *limits;
+        self
+    }
+
+    pub fn options(mut self, options: &CompileOptions) -> Self {
+        self.options = *options;
+        self
+    }
+
+    /// Global preamble for every worker, such as platform defines
+    pub fn preamble(mut self, source: &str) -> Self {
+        self.preamble = Some(source.to_string());
+        self
+    }
+
+    pub fn include_dir(mut self, dir: &str) -> Self {
+        self.include_dirs.push(dir.to_string());
+        self
+    }
+
+    pub fn virtual_include(mut self, name: &str, contents: &str) -> Self {
+        self.virtual_includes
+            .push((name.to_string(), contents.to_string()));
+        self
+    }
+
+    /// Results come back in permutation order whatever the thread count.
+    /// Permutations that break one of the space's constraints are skipped.
+    pub fn build(&self, space: &KeywordSpace) -> Vec<VariantResult> {
+        let enumeration = space.enumerate();
+        if enumeration.pruned > 0 {
+            println!(
+                "Pruned {} of {} permutations by keyword constraints",
+                enumeration.pruned,
+                space.len()
+            );
+        }
+        self.run(space, enumeration.permutations)
+    }
+
+    /// Compiles only the keyword sets recorded for one shader pass. Recorded
+    /// sets that the keyword space can't express, or that break one of its
+    /// constraints, are skipped with a warning.
+    pub fn build_collected(
+        &self,
+        space: &KeywordSpace,
+        collection: &VariantCollection,
+        shader: &str,
+        pass: &str,
+    ) -> Vec<VariantResult> {
+        let mut permutations: Vec<Vec<usize>> = Vec::new();
+        for variant in collection.for_pass(shader, pass) {
+            match space.indices(&variant.keywords) {
+                Ok(indices) if !space.allows(&indices) => println!(
+                    "Warning: recorded variant [{}] of {}/{} breaks a keyword constraint",
+                    variant.keywords.join(", "),
+                    shader,
+                    pass
+                ),
+                Ok(indices) => {
+                    if !permutations.contains(&indices) {
+                        permutations.push(indices);
+                    }
+                }
+                Err(e) => println!(
+                    "Warning: recorded variant [{}] of {}/{} doesn't fit the keyword space: {}",
+                    variant.keywords.join(", "),
+                    shader,
+                    pass,
+                    e
+                ),
+            }
+        }
+        println!(
+            "Compiling {} recorded of {} possible permutations",
+            permutations.len(),
+            space.len()
+        );
+        self.run(space, permutations)
+    }
+
+    fn run(&self, space: &KeywordSpace, permutations: Vec<Vec<usize>>) -> Vec<VariantResult> {
+        let sources = self
+            .stages
+            .iter()
+            .map(|(_, source)| source)
+            .chain(&self.preamble)
+            .chain(self.virtual_includes.iter().map(|(_, contents)| contents));
+        for warning in KeywordUsage::check(space, sources.map(|s| s.as_str())).warnings() {
+            println!("Warning: {}", warning);
+        }
+
+        let permutations: Vec<(usize, Vec<usize>, Vec<String>)> = permutations
+            .into_iter()
+            .map(|indices| {
+                let index = space.index(&indices).unwrap();
+                let keywords = space.keywords(&indices).unwrap();
+                (index, indices, keywords)
+            })
+            .collect();
+        let threads = self.threads.min(permutations.len()).max(1);
+        let enabled = ShaderCompiler::thread_enabled_keywords();
+
+        let mut results: Vec<VariantResult> = thread::scope(|scope| {
+            let workers: Vec<_> = (0..threads)
+                .map(|worker| {
+                    let permutations = &permutations;
+                    scope.spawn(move || {
+                        let mut compiler = ShaderCompiler::new(&self.limits);
+                        compiler.set_enabled_keywords(&enabled);
+                        if let Some(preamble) = &self.preamble {
+                            compiler.set_preamble(preamble);
+                        }
+                        // Include paths are thread-local on the C++ side
+                        for dir in &self.include_dirs {
+                            compiler.add_include_dir(dir);
+                        }
+                        for (name, contents) in &self.virtual_includes {
+                            compiler.add_virtual_include(name, contents);
+                        }
+                        // Enum constants valued keywords such as
+                        // SHADOW_FILTER=PCSS refer to
+                        let mut compiler =
+                            compiler.push_preamble(PreambleScope::Shader, &space.constants());
+                        let mut results = Vec::new();
+                        for i in (worker..permutations.len()).step_by(threads) {
+                            let (index, indices, keywords) = &permutations[i];
+                            results.push(self.compile(&mut compiler, *index, indices, keywords));
+                        }
+                        results
+                    })
+                })
+                .collect();
+
+            workers
+                .into_iter()
+                .flat_map(|w| w.join().unwrap())
+                .collect()
+        });
+
+        results.sort_by_key(|r| r.index);
+        results
+    }
+
+    fn compile(
+        &self,
+        compiler: &mut ShaderCompiler,
+        index: usize,
+        indices: &[usize],
+        keywords: &[String],
+    ) -> VariantResult {
+        let program = compiler.create_program();
+        let mut compiled = true;
+        for kw in keywords {
+            if let Err(e) = compiler.add_keyword(program, kw) {
+                println!("Variant {}: {}", index, e);
+                compiled = false;
+            }
+        }
+
+        for (stage, source) in &self.stages {
+            if !compiled {
+                break;
+            }
+            let handle = compiler.compile(*stage, source, &self.options);
+            if handle == 0 {
+                compiled = false;
+                break;
+            }
+            compiler.add(program, handle);
+        }
+        // The keywords ID is only set by a compile, a failed permutation would
+        // read the one the previous permutation on this thread left
+        let mut all_keywords = VariantKey::canonical(keywords);
+        if compiled {
+            match compiler.get_variant_keywords() {
+                Ok(k) => all_keywords = k,
+                Err(e) => {
+                    println!("Variant {}: {}", index, e);
+                    compiled = false;
+                }
+            }
+        }
+        // Always link, it resets the keyword state for the next permutation
+        let linked = compiler.link(program) && compiled;
+
+        let mut stages = Vec::new();
+        if linked {
+            for (stage, _) in &self.stages {
+                match compiler.get_spirv_for_stage(program, *stage, &self.options) {
+                    Ok(module) => stages.push((*stage, module)),
+                    Err(e) => println!("Variant {}: {}", index, e),
+                }
+            }
+        }
+
+        compiler.delete_program(program);
+        compiler.clear_shader_cache();
+
+        VariantResult {
+            index,
+            indices: indices.to_vec(),
+            keywords: keywords.to_vec(),
+            key: VariantKey::new(&all_keywords),
+            all_keywords,
+            linked,
+            stages,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHADER: &str = "#version 450
+#ifdef BROKEN_VARIANT
+#error broken variant
+#endif
+layout(location = 0) out vec4 colour;
+void main() {
+    colour = vec4(1.0);
+}
+";
+
+    #[test]
+    fn failed_permutations_keep_their_own_keywords() {
+        let mut space = KeywordSpace::new();
+        space
+            .add_axis("tint", &["TINTED_VARIANT", "_"])
+            .and_then(|s| s.add_axis("broken", &["_", "BROKEN_VARIANT"]))
+            .unwrap();
+        // One thread, so the broken permutations compile right after a good one
+        let results = VariantBuilder::new()
+            .threads(1)
+            .stage(Stage::FragmentStage, SHADER)
+            .build(&space);
+        assert_eq!(results.len(), 4);
+
+        assert!(results[0].linked);
+        assert_eq!(results[0].all_keywords, ["TINTED_VARIANT"]);
+        for result in results.iter().filter(|r| r.indices[1] == 1) {
+            assert!(!result.linked);
+            assert!(result.stages.is_empty());
+            assert_eq!(result.all_keywords, VariantKey::canonical(&result.keywords));
+            assert_eq!(result.key, VariantKey::new(&result.all_keywords));
+        }
+    }
+}
@@ -0,0 +1,451 @@
+use crate::reflect::{BaseType, BlockMember, Resource, ResourceKind, ShaderReflection};
+use crate::variant::VariantKey;
+use std::fmt::Write;
+
+// Strict and reserved Rust keywords, escaped as raw identifiers
+const KEYWORDS: &[&str] = &[
+    "abstract", "as", "async", "await", "become", "box", "break", "const", "continue", "do", "dyn",
+    "else", "enum", "extern", "false", "final", "fn", "for", "gen", "if", "impl", "in", "let",
+    "loop", "macro", "match", "mod", "move", "mut", "override", "priv", "pub", "ref", "return",
+    "static", "struct", "trait", "true", "try", "type", "typeof", "unsafe", "unsized", "use",
+    "virtual", "where", "while", "yield",
+];
+
+// Keywords that can't be raw identifiers, suffixed instead
+const PATH_KEYWORDS: &[&str] = &["crate", "self", "Self", "super"];
+
+// One Rust field of a generated block struct
+struct Field {
+    name: String,
+    offset: u32,
+    size: u32,
+    ty: String,
+}
+
+struct Layout {
+    block: String,
+    kind: ResourceKind,
+    size: u32,
+    fields: Vec<Field>,
+    variants: Vec<VariantKey>,
+}
+
+impl Layout {
+    fn matches(&self, other: &Layout) -> bool {
+        self.block == other.block
+            && self.size == other.size
+            && self.fields.len() == other.fields.len()
+            && self
+                .fields
+                .iter()
+                .zip(&other.fields)
+                .all(|(a, b)| a.name == b.name && a.offset == b.offset && a.ty == b.ty)
+    }
+}
+
+/// Generates `#[repr(C)]` host structs for the uniform, storage and push
+/// constant blocks of each (variant key, reflection) pair. Blocks with the same
+/// layout in several variants share one struct, whose `VARIANT` constant
+/// lists the variants it is valid for.
+pub fn generate_block_structs(variants: &[(VariantKey, ShaderReflection)]) -> String {
+    let mut layouts: Vec<Layout> = Vec::new();
+    for (id, reflection) in variants {
+        for resource in &reflection.resources {
+            let layout = match layout_for(resource) {
+                Some(l) => l,
+                None => continue,
+            };
+            match layouts.iter_mut().find(|l| l.matches(&layout)) {
+                Some(existing) => {
+                    if !existing.variants.contains(id) {
+                        existing.variants.push(*id);
+                    }
+                }
+                None => layouts.push(Layout {
+                    variants: vec![*id],
+                    ..layout
+                }),
+            }
+        }
+    }
+
+    let mut out = String::new();
+    out.push_str("// Generated by shader-one from reflected block layouts, do not edit.\n");
+    // Blocks are told apart by their Rust name, UBO and Ubo both become Ubo
+    let names: Vec<String> = layouts.iter().map(|l| type_name(&l.block)).collect();
+    for (i, layout) in layouts.iter().enumerate() {
+        let mut name = names[i].clone();
+        if names.iter().filter(|n| **n == name).count() > 1 {
+            let index = names[..i].iter().filter(|n| **n == name).count();
+            name.push_str(&index.to_string());
+        }
+        write_struct(&mut out, &name, layout);
+    }
+    out
+}
+
+fn layout_for(resource: &Resource) -> Option<Layout> {
+    match resource.kind {
+        ResourceKind::UniformBuffer | ResourceKind::StorageBuffer | ResourceKind::PushConstant => {}
+        _ => return None,
+    }
+
+    // Arrays of structs are written as bytes, so the members reflected for
+    // their first element are left out
+    let mut leaves: Vec<&BlockMember> = resource
+        .members
+        .iter()
+        .filter(|m| m.base_type != BaseType::Struct || m.array_size != 1)
+        .filter(|m| !m.name.contains('['))
+        .collect();
+    leaves.sort_by_key(|m| m.offset);
+
+    let mut fields = Vec::new();
+    for member in leaves {
+        // Runtime sized arrays can't live in a fixed size struct, the host
+        // indexes past the end of the block instead
+        if member.array_size == 0 {
+            break;
+        }
+        fields.push(Field {
+            name: field_name(&member.name),
+            offset: member.offset,
+            size: member.size,
+            ty: member_type(member),
+        });
+    }
+    disambiguate(&mut fields);
+
+    Some(Layout {
+        block: resource.name.clone(),
+        kind: resource.kind,
+        size: resource.size,
+        fields,
+        variants: Vec::new(),
+    })
+}
+
+fn write_struct(out: &mut String, name: &str, layout: &Layout) {
+    let rule = match layout.kind {
+        ResourceKind::UniformBuffer => "std140",
+        _ => "std430",
+    };
+
+    writeln!(out).unwrap();
+    writeln!(out, "/// `{}` block, {} layout", layout.block, rule).unwrap();
+    writeln!(out, "#[repr(C)]").unwrap();
+    writeln!(out, "#[derive(Copy, Clone)]").unwrap();
+    writeln!(out, "pub struct {} {{", name).unwrap();
+
+    let mut cursor = 0;
+    let mut padding = 0;
+    for field in &layout.fields {
+        if field.offset > cursor {
+            let pad = pad_name(&layout.fields, &mut padding);
+            writeln!(out, "    pub {}: [u8; {}],", pad, field.offset - cursor).unwrap();
+        }
+        writeln!(out, "    pub {}: {},", field.name, field.ty).unwrap();
+        cursor = field.offset + field.size;
+    }
+    if layout.size > cursor {
+        let pad = pad_name(&layout.fields, &mut padding);
+        writeln!(out, "    pub {}: [u8; {}],", pad, layout.size - cursor).unwrap();
+    }
+    writeln!(out, "}}").unwrap();
+    writeln!(out).unwrap();
+
+    writeln!(out, "impl {} {{", name).unwrap();
+    writeln!(
+        out,
+        "    /// Variant keys whose `{}` block has this layout",
+        layout.block
+    )
+    .unwrap();
+    let ids: Vec<String> = layout
+        .variants
+        .iter()
+        .map(|key| format!("0x{}", key))
+        .collect();
+    writeln!(
+        out,
+        "    pub const VARIANT: &'static [u128] = &[{}];",
+        ids.join(", ")
+    )
+    .unwrap();
+    writeln!(out, "}}").unwrap();
+    writeln!(out).unwrap();
+
+    for field in &layout.fields {
+        writeln!(
+            out,
+            "const _: () = assert!(std::mem::offset_of!({}, {}) == {});",
+            name, field.name, field.offset
+        )
+        .unwrap();
+    }
+    writeln!(
+        out,
+        "const _: () = assert!(std::mem::size_of::<{}>() == {});",
+        name,
+        layout.size.max(cursor)
+    )
+    .unwrap();
+}
+
+// Next free padding field, skipping any a block member already named
+fn pad_name(fields: &[Field], padding: &mut u32) -> String {
+    loop {
+        let name = format!("_pad{}", padding);
+        *padding += 1;
+        if fields.iter().all(|f| f.name != name) {
+            return name;
+        }
+    }
+}
+
+fn scalar(base_type: BaseType) -> (&'static str, u32) {
+    match base_type {
+        // GLSL bools are 32 bits wide inside blocks
+        BaseType::Bool | BaseType::UInt => ("u32", 4),
+        BaseType::Int => ("i32", 4),
+        BaseType::Int8 => ("i8", 1),
+        BaseType::UInt8 => ("u8", 1),
+        BaseType::Int16 => ("i16", 2),
+        BaseType::UInt16 | BaseType::Half => ("u16", 2),
+        BaseType::Int64 => ("i64", 8),
+        BaseType::UInt64 => ("u64", 8),
+        BaseType::Double => ("f64", 8),
+        BaseType::Float => ("f32", 4),
+        _ => ("u8", 1),
+    }
+}
+
+// Rust type for one element (ignoring any array) of a block member
+fn element_type(member: &BlockMember) -> (String, u32) {
+    if member.base_type == BaseType::Struct {
+        let size = if member.array_size > 1 {
+            member.array_stride
+        } else {
+            member.size
+        };
+        return (format!("[u8; {}]", size), size);
+    }
+
+    let (ty, width) = scalar(member.base_type);
+    if member.columns > 1 {
+        // Columns (or rows when row major) are padded out to the matrix stride
+        let vectors = if member.row_major {
+            member.vecsize
+        } else {
+            member.columns
+        };
+        let components = member.matrix_stride / width;
+        let size = member.matrix_stride * vectors;
+        (format!("[[{}; {}]; {}]", ty, components, vectors), size)
+    } else if member.vecsize > 1 {
+        (
+            format!("[{}; {}]", ty, member.vecsize),
+            width * member.vecsize,
+        )
+    } else {
+        (ty.to_string(), width)
+    }
+}
+
+fn member_type(member: &BlockMember) -> String {
+    let (element, size) = element_type(member);
+    if member.array_size <= 1 {
+        return element;
+    }
+
+    let stride = member.array_stride;
+    if stride == size {
+        format!("[{}; {}]", element, member.array_size)
+    } else if member.columns <= 1 && member.base_type != BaseType::Struct {
+        // std140 rounds scalar and vector array elements up to a vec4
+        let (ty, width) = scalar(member.base_type);
+        format!("[[{}; {}]; {}]", ty, stride / width, member.array_size)
+    } else {
+        format!("[[u8; {}]; {}]", stride, member.array_size)
+    }
+}
+
+fn field_name(name: &str) -> String {
+    let mut out = String::new();
+    let mut previous_lower = false;
+    for c in name.chars() {
+        if c == '.' || c == '_' {
+            out.push('_');
+            previous_lower = false;
+        } else if c.is_ascii_uppercase() {
+            if previous_lower {
+                out.push('_');
+            }
+            out.push(c.to_ascii_lowercase());
+            previous_lower = false;
+        } else {
+            out.push(c);
+            previous_lower = c.is_ascii_lowercase() || c.is_ascii_digit();
+        }
+    }
+    if out.is_empty() || out == "_" || PATH_KEYWORDS.contains(&out.as_str()) {
+        out.push('_');
+        out
+    } else if KEYWORDS.contains(&out.as_str()) {
+        format!("r#{}", out)
+    } else {
+        out
+    }
+}
+
+// Flattening can give two members one name, `a.b_c` and `a_b.c` are both
+// a_b_c, so later ones get a numbered suffix
+fn disambiguate(fields: &mut [Field]) {
+    let mut used: Vec<String> = Vec::new();
+    for field in fields.iter_mut() {
+        if used.contains(&field.name) {
+            let base = field.name.trim_start_matches("r#").to_string();
+            let mut n = 1;
+            while used.contains(&format!("{}_{}", base, n)) {
+                n += 1;
+            }
+            field.name = format!("{}_{}", base, n);
+        }
+        used.push(field.name.clone());
+    }
+}
+
+fn type_name(block: &str) -> String {
+    let mut out = String::new();
+    for part in block.split('_').filter(|p| !p.is_empty()) {
+        let mut chars = part.chars();
+        if let Some(first) = chars.next() {
+            out.push(first.to_ascii_uppercase());
+            let rest: String = chars.collect();
+            if rest.chars().all(|c| !c.is_ascii_lowercase()) {
+                out.push_str(&rest.to_ascii_lowercase());
+            } else {
+                out.push_str(&rest);
+            }
+        }
+    }
+    if out.is_empty() {
+        out.push_str("Block");
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn float(name: &str, offset: u32) -> BlockMember {
+        BlockMember {
+            name: name.to_string(),
+            offset,
+            size: 4,
+            base_type: BaseType::Float,
+            vecsize: 1,
+            columns: 1,
+            array_size: 1,
+            array_stride: 0,
+            matrix_stride: 0,
+            row_major: false,
+        }
+    }
+
+    fn block(members: Vec<BlockMember>) -> Resource {
+        Resource {
+            kind: ResourceKind::UniformBuffer,
+            name: "UBO".to_string(),
+            set: 0,
+            binding: 0,
+            array_size: 1,
+            input_attachment_index: 0,
+            size: 4 * members.len() as u32,
+            members,
+        }
+    }
+
+    #[test]
+    fn keywords_are_escaped() {
+        assert_eq!(field_name("type"), "r#type");
+        assert_eq!(field_name("loop"), "r#loop");
+        assert_eq!(field_name("where"), "r#where");
+        assert_eq!(field_name("self"), "self_");
+        assert_eq!(field_name("Self"), "self_");
+        assert_eq!(field_name("crate"), "crate_");
+        assert_eq!(field_name("super"), "super_");
+        assert_eq!(field_name("viewPos"), "view_pos");
+    }
+
+    #[test]
+    fn flattened_names_are_disambiguated() {
+        let layout = layout_for(&block(vec![float("a.b_c", 0), float("a_b.c", 4)])).unwrap();
+        let names: Vec<&str> = layout.fields.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(names, ["a_b_c", "a_b_c_1"]);
+    }
+
+    #[test]
+    fn generated_struct_uses_escaped_names() {
+        let resources = vec![block(vec![float("self", 0), float("fn", 4)])];
+        let code = generate_block_structs(&[(VariantKey(1), reflection(resources))]);
+        assert!(code.contains("pub self_: f32,"));
+        assert!(code.contains("pub r#fn: f32,"));
+        assert!(code.contains("offset_of!(Ubo, r#fn) == 4"));
+    }
+
+    #[test]
+    fn struct_array_elements_are_left_to_the_array() {
+        let lights = BlockMember {
+            name: "lights".to_string(),
+            size: 64,
+            base_type: BaseType::Struct,
+            array_size: 2,
+            array_stride: 32,
+            ..float("lights", 0)
+        };
+        let layout = layout_for(&block(vec![
+            lights,
+            float("lights[0].range", 28),
+            float("exposure", 64),
+        ]))
+        .unwrap();
+        let fields: Vec<(&str, &str)> = layout
+            .fields
+            .iter()
+            .map(|f| (f.name.as_str(), f.ty.as_str()))
+            .collect();
+        assert_eq!(fields, [("lights", "[[u8; 32]; 2]"), ("exposure", "f32")]);
+    }
+
+    fn reflection(resources: Vec<Resource>) -> ShaderReflection {
+        ShaderReflection {
+            resources,
+            inputs: Vec::new(),
+            outputs: Vec::new(),
+            entry_points: Vec::new(),
+            workgroup_size: None,
+        }
+    }
+
+    #[test]
+    fn blocks_with_the_same_rust_name_are_numbered() {
+        let mut upper = block(vec![float("a", 0)]);
+        upper.name = "UBO".to_string();
+        let mut camel = block(vec![float("b", 0)]);
+        camel.name = "Ubo".to_string();
+        let code = generate_block_structs(&[(VariantKey(1), reflection(vec![upper, camel]))]);
+        assert!(code.contains("pub struct Ubo0 {"));
+        assert!(code.contains("pub struct Ubo1 {"));
+    }
+
+    #[test]
+    fn padding_skips_member_names() {
+        let mut resource = block(vec![float("_pad0", 0), float("value", 8)]);
+        resource.size = 16;
+        let code = generate_block_structs(&[(VariantKey(1), reflection(vec![resource]))]);
+        assert!(code.contains("pub _pad0: f32,\n    pub _pad1: [u8; 4],"));
+        assert!(code.contains("pub value: f32,\n    pub _pad2: [u8; 4],"));
+    }
+}
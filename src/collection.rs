@@ -0,0 +1,175 @@
+use crate::variant::VariantKey;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// A keyword set one pass of a shader was used with at runtime
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CollectedVariant {
+    pub shader: String,
+    pub pass: String,
+    /// Sorted and deduplicated, as `VariantKey::canonical`
+    pub keywords: Vec<String>,
+}
+
+impl CollectedVariant {
+    pub fn key(&self) -> VariantKey {
+        VariantKey::new(&self.keywords)
+    }
+}
+
+/// The variants a game actually used, recorded while it runs and merged
+/// across play sessions, so builds can compile just those.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct VariantCollection {
+    variants: BTreeSet<CollectedVariant>,
+}
+
+impl VariantCollection {
+    pub fn new() -> Self {
+        VariantCollection::default()
+    }
+
+    /// Returns false if the combination was already recorded
+    pub fn record<S: AsRef<str>>(&mut self, shader: &str, pass: &str, keywords: &[S]) -> bool {
+        self.variants.insert(CollectedVariant {
+            shader: shader.to_string(),
+            pass: pass.to_string(),
+            keywords: VariantKey::canonical(keywords),
+        })
+    }
+
+    pub fn merge(&mut self, other: &VariantCollection) {
+        self.variants.extend(other.variants.iter().cloned());
+    }
+
+    pub fn len(&self) -> usize {
+        self.variants.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.variants.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &CollectedVariant> {
+        self.variants.iter()
+    }
+
+    /// Recorded keyword sets of one shader pass
+    pub fn for_pass<'a>(
+        &'a self,
+        shader: &'a str,
+        pass: &'a str,
+    ) -> impl Iterator<Item = &'a CollectedVariant> {
+        self.variants
+            .iter()
+            .filter(move |v| v.shader == shader && v.pass == pass)
+    }
+
+    pub fn from_json(text: &str) -> Result<Self, CollectionError> {
+        serde_json::from_str(text).map_err(CollectionError::Json)
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap()
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, CollectionError> {
+        let text = fs::read_to_string(path).map_err(CollectionError::Io)?;
+        VariantCollection::from_json(&text)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), CollectionError> {
+        fs::write(path, self.to_json()).map_err(CollectionError::Io)
+    }
+
+    /// Merges a session's recording into the collection at `path`, creating
+    /// it if there isn't one yet
+    pub fn merge_into_file<P: AsRef<Path>>(&self, path: P) -> Result<(), CollectionError> {
+        let path = path.as_ref();
+        let mut merged = match VariantCollection::load(path) {
+            Ok(collection) => collection,
+            Err(CollectionError::Io(e)) if e.kind() == io::ErrorKind::NotFound => {
+                VariantCollection::new()
+            }
+            Err(e) => return Err(e),
+        };
+        merged.merge(self);
+        merged.save(path)
+    }
+}
+
+#[derive(Debug)]
+pub enum CollectionError {
+    Io(io::Error),
+    Json(serde_json::Error),
+}
+
+impl fmt::Display for CollectionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CollectionError::Io(e) => write!(f, "cannot access variant collection: {}", e),
+            CollectionError::Json(e) => write!(f, "invalid variant collection: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for CollectionError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_each_combination_once() {
+        let mut collection = VariantCollection::new();
+        assert!(collection.record("water", "forward", &["LIT", "FOG"]));
+        assert!(!collection.record("water", "forward", &["FOG", "LIT", "FOG"]));
+        assert!(collection.record("water", "shadow", &["FOG", "LIT"]));
+        assert_eq!(collection.len(), 2);
+        let forward: Vec<_> = collection.for_pass("water", "forward").collect();
+        assert_eq!(forward.len(), 1);
+        assert_eq!(forward[0].keywords, ["FOG", "LIT"]);
+        assert_eq!(forward[0].key(), VariantKey::new(&["LIT", "FOG"]));
+    }
+
+    #[test]
+    fn merges_sessions() {
+        let mut first = VariantCollection::new();
+        first.record("water", "forward", &["LIT"]);
+        let mut second = VariantCollection::new();
+        second.record("water", "forward", &["LIT"]);
+        second.record("water", "forward", &["UNLIT"]);
+        first.merge(&second);
+        assert_eq!(first.len(), 2);
+
+        let path = std::env::temp_dir().join(format!("collection-{}.json", std::process::id()));
+        first.merge_into_file(&path).unwrap();
+        let mut third = VariantCollection::new();
+        third.record("sky", "forward", &[] as &[&str]);
+        third.merge_into_file(&path).unwrap();
+        let merged = VariantCollection::load(&path);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(merged.unwrap().len(), 3);
+    }
+
+    #[test]
+    fn round_trips_through_json() {
+        let mut collection = VariantCollection::new();
+        collection.record("water", "forward", &["LIT", "FOG"]);
+        collection.record("sky", "forward", &[] as &[&str]);
+        let read = VariantCollection::from_json(&collection.to_json()).unwrap();
+        assert_eq!(read, collection);
+
+        let extra = r#"{"variants": [], "version": 2}"#;
+        assert!(matches!(
+            VariantCollection::from_json(extra),
+            Err(CollectionError::Json(_))
+        ));
+    }
+}
@@ -0,0 +1,304 @@
+#include "cross.h"
+
+#include <cstdio>
+#include <deque>
+#include <functional>
+#include <vector>
+
+#include "reflect.h"
+
+thread_local std::string crossSource;
+thread_local std::deque<std::string> manifestNames;
+thread_local std::vector<RawBinding> manifest;
+thread_local std::deque<std::string> compatNames;
+thread_local std::vector<RawCombinedSampler> compatSamplers;
+thread_local std::vector<RawFlattenedBlock> compatBlocks;
+thread_local std::vector<RawFlattenedMember> compatMembers;
+thread_local RawGlesCompat glesCompat;
+
+static const char* KeepCompatName(const std::string& name) {
+  compatNames.push_back(name);
+  return compatNames.back().c_str();
+}
+
+// Rewrites the module for targets without separate samplers or uniform
+// blocks, recording the new names for the GL backend
+static void ApplyGlesCompat(spirv_cross::CompilerGLSL& glsl) {
+  spirv_cross::VariableID dummy =
+      glsl.build_dummy_sampler_for_combined_images();
+  if (dummy != 0)
+    glsl.set_name(dummy, "SPIRV_Cross_DummySampler");
+
+  glsl.build_combined_image_samplers();
+  for (auto& remap : glsl.get_combined_image_samplers()) {
+    std::string image = glsl.get_name(remap.image_id);
+    std::string sampler = glsl.get_name(remap.sampler_id);
+    std::string name = "SPIRV_Cross_Combined" + image + sampler;
+    glsl.set_name(remap.combined_id, name);
+    glsl.set_decoration(
+        remap.combined_id, spv::DecorationBinding,
+        glsl.get_decoration(remap.image_id, spv::DecorationBinding));
+    compatSamplers.push_back(RawCombinedSampler{
+        KeepCompatName(name), KeepCompatName(image), KeepCompatName(sampler)});
+  }
+
+  spirv_cross::ShaderResources resources = glsl.get_shader_resources();
+  for (auto& ubo : resources.uniform_buffers) {
+    glsl.flatten_buffer_block(ubo.id);
+
+    // The flattened array takes the name of the block type
+    const spirv_cross::SPIRType& type = glsl.get_type(ubo.base_type_id);
+    RawFlattenedBlock block{};
+    block.block = KeepCompatName(ubo.name);
+    block.array = KeepCompatName(glsl.get_name(ubo.base_type_id));
+    block.vectors = (uint32_t)(glsl.get_declared_struct_size(type) + 15) / 16;
+    block.firstMember = compatMembers.size();
+    for (uint32_t i = 0; i < type.member_types.size(); ++i) {
+      uint32_t offset = glsl.type_struct_member_offset(type, i);
+      compatMembers.push_back(RawFlattenedMember{
+          KeepCompatName(glsl.get_member_name(type.self, i)), offset,
+          offset / 16, (offset % 16) / 4});
+    }
+    block.memberCount = compatMembers.size() - block.firstMember;
+    compatBlocks.push_back(block);
+  }
+}
+
+static const BindingSlot* FindRemap(const BindingSlot* remaps,
+                                    std::size_t remapCount,
+                                    uint32_t set,
+                                    uint32_t binding) {
+  for (std::size_t i = 0; i < remapCount; ++i) {
+    if (remaps[i].set == set && remaps[i].binding == binding)
+      return &remaps[i];
+  }
+  return nullptr;
+}
+
+// Register SPIRV-Cross gives the resource in its register class, a combined
+// image sampler is reported by its t register
+static uint32_t HlslRegister(spirv_cross::Compiler& compiler,
+                             const spirv_cross::Resource& resource,
+                             int kind,
+                             const BindingSlot& slot) {
+  switch (kind) {
+    case EResourceUniformBuffer:
+      return slot.hlslCbv;
+    case EResourceStorageBuffer:
+      // Read only buffers become ByteAddressBuffers
+      if (compiler.get_buffer_block_flags(resource.id)
+              .get(spv::DecorationNonWritable))
+        return slot.hlslSrv;
+      return slot.hlslUav;
+    case EResourceStorageImage:
+      return slot.hlslUav;
+    case EResourceSeparateSampler:
+      return slot.hlslSampler;
+    default:
+      return slot.hlslSrv;
+  }
+}
+
+// Records every descriptor resource of the compiled source, with the slot
+// picked by the callback for the target
+static void BuildManifest(
+    spirv_cross::Compiler& compiler,
+    const std::function<void(const spirv_cross::Resource&, RawBinding&)>&
+        assign) {
+  manifestNames.clear();
+  manifest.clear();
+  spirv_cross::ShaderResources resources =
+      compiler.get_shader_resources(compiler.get_active_interface_variables());
+
+  const std::pair<const spirv_cross::SmallVector<spirv_cross::Resource>*,
+                  ResourceKind>
+      categories[] = {
+          {&resources.uniform_buffers, EResourceUniformBuffer},
+          {&resources.storage_buffers, EResourceStorageBuffer},
+          {&resources.sampled_images, EResourceSampledImage},
+          {&resources.separate_images, EResourceSeparateImage},
+          {&resources.separate_samplers, EResourceSeparateSampler},
+          {&resources.storage_images, EResourceStorageImage},
+          {&resources.subpass_inputs, EResourceSubpassInput},
+      };
+
+  for (auto& category : categories) {
+    for (auto& resource : *category.first) {
+      manifestNames.push_back(resource.name);
+      RawBinding raw{};
+      raw.kind = category.second;
+      raw.name = manifestNames.back().c_str();
+      raw.set =
+          compiler.get_decoration(resource.id, spv::DecorationDescriptorSet);
+      raw.binding =
+          compiler.get_decoration(resource.id, spv::DecorationBinding);
+      raw.slot = raw.binding;
+      raw.space = raw.set;
+      raw.sampler = UINT32_MAX;
+      assign(resource, raw);
+      manifest.push_back(raw);
+    }
+  }
+}
+
+extern "C" const char* DecompileToGLSL(unsigned int* ptr,
+                                       std::size_t length,
+                                       const GlslOptions* options) {
+  compatNames.clear();
+  compatSamplers.clear();
+  compatBlocks.clear();
+  compatMembers.clear();
+
+  try {
+    spirv_cross::CompilerGLSL glsl(ptr, length);
+    spirv_cross::CompilerGLSL::Options common = glsl.get_common_options();
+    common.version = options->version;
+    common.es = options->es;
+    common.vulkan_semantics = options->vulkanSemantics;
+    common.vertex.flip_vert_y = options->flipVertY;
+    glsl.set_common_options(common);
+    if (options->glesCompat)
+      ApplyGlesCompat(glsl);
+    crossSource = glsl.compile();
+    BuildManifest(glsl, [](const spirv_cross::Resource&, RawBinding&) {});
+  } catch (const spirv_cross::CompilerError& e) {
+    printf("GLSL Decompile Failed!\n%s\n", e.what());
+    return nullptr;
+  }
+  return crossSource.c_str();
+}
+
+extern "C" const char* DecompileToHLSL(unsigned int* ptr,
+                                       std::size_t length,
+                                       const HlslOptions* options,
+                                       const BindingSlot* remaps,
+                                       std::size_t remapCount) {
+  try {
+    spirv_cross::CompilerHLSL hlsl(ptr, length);
+    spirv_cross::CompilerHLSL::Options hlslOptions = hlsl.get_hlsl_options();
+    hlslOptions.shader_model = options->shaderModel;
+    hlslOptions.point_size_compat = options->pointSizeCompat;
+    hlsl.set_hlsl_options(hlslOptions);
+
+    spv::ExecutionModel model = hlsl.get_execution_model();
+    for (std::size_t i = 0; i < remapCount; ++i) {
+      spirv_cross::HLSLResourceBinding binding;
+      binding.stage = model;
+      binding.desc_set = remaps[i].set;
+      binding.binding = remaps[i].binding;
+      // The resource type picks the class, a combined image sampler uses
+      // both srv and sampler
+      binding.cbv.register_binding = remaps[i].hlslCbv;
+      binding.srv.register_binding = remaps[i].hlslSrv;
+      binding.uav.register_binding = remaps[i].hlslUav;
+      binding.sampler.register_binding = remaps[i].hlslSampler;
+      binding.cbv.register_space = remaps[i].hlslSpace;
+      binding.srv.register_space = remaps[i].hlslSpace;
+      binding.uav.register_space = remaps[i].hlslSpace;
+      binding.sampler.register_space = remaps[i].hlslSpace;
+      hlsl.add_hlsl_resource_binding(binding);
+    }
+    crossSource = hlsl.compile();
+
+    BuildManifest(hlsl, [&](const spirv_cross::Resource& resource,
+                            RawBinding& raw) {
+      const BindingSlot* remap =
+          FindRemap(remaps, remapCount, raw.set, raw.binding);
+      if (raw.kind == EResourceSampledImage)
+        raw.sampler = raw.binding;
+      if (remap &&
+          hlsl.is_hlsl_resource_binding_used(model, raw.set, raw.binding)) {
+        raw.slot = HlslRegister(hlsl, resource, raw.kind, *remap);
+        raw.space = remap->hlslSpace;
+        if (raw.kind == EResourceSampledImage)
+          raw.sampler = remap->hlslSampler;
+        raw.remapped = true;
+      }
+      // Register spaces arrived in SM 5.1, SPIRV-Cross leaves them out below
+      if (options->shaderModel < 51)
+        raw.space = 0;
+    });
+  } catch (const spirv_cross::CompilerError& e) {
+    printf("HLSL Decompile Failed!\n%s\n", e.what());
+    return nullptr;
+  }
+  return crossSource.c_str();
+}
+
+extern "C" const char* DecompileToMetal(unsigned int* ptr,
+                                        std::size_t length,
+                                        const MslOptions* options,
+                                        const BindingSlot* remaps,
+                                        std::size_t remapCount) {
+  try {
+    spirv_cross::CompilerMSL msl(ptr, length);
+    spirv_cross::CompilerMSL::Options mslOptions = msl.get_msl_options();
+    mslOptions.platform = options->platform == 0
+                              ? spirv_cross::CompilerMSL::Options::iOS
+                              : spirv_cross::CompilerMSL::Options::macOS;
+    mslOptions.msl_version =
+        spirv_cross::CompilerMSL::Options::make_msl_version(
+            options->major, options->minor, options->patch);
+    mslOptions.argument_buffers = options->argumentBuffers;
+    msl.set_msl_options(mslOptions);
+
+    spv::ExecutionModel model = msl.get_execution_model();
+    for (std::size_t i = 0; i < remapCount; ++i) {
+      spirv_cross::MSLResourceBinding binding;
+      binding.stage = model;
+      binding.desc_set = remaps[i].set;
+      binding.binding = remaps[i].binding;
+      binding.msl_buffer = remaps[i].mslBuffer;
+      binding.msl_texture = remaps[i].mslTexture;
+      binding.msl_sampler = remaps[i].mslSampler;
+      msl.add_msl_resource_binding(binding);
+    }
+    crossSource = msl.compile();
+
+    BuildManifest(msl, [&](const spirv_cross::Resource& resource,
+                           RawBinding& raw) {
+      raw.slot = msl.get_automatic_msl_resource_binding(resource.id);
+      raw.sampler =
+          msl.get_automatic_msl_resource_binding_secondary(resource.id);
+      raw.space = 0;
+      raw.remapped =
+          msl.is_msl_resource_binding_used(model, raw.set, raw.binding) &&
+          FindRemap(remaps, remapCount, raw.set, raw.binding) != nullptr;
+    });
+  } catch (const spirv_cross::CompilerError& e) {
+    printf("Metal Decompile Failed!\n%s\n", e.what());
+    return nullptr;
+  }
+  return crossSource.c_str();
+}
+
+// SPIRV-Cross's JSON description of the module, for tooling outside Rust
+extern "C" const char* DecompileToReflection(unsigned int* ptr,
+                                             std::size_t length) {
+  try {
+    spirv_cross::CompilerReflection reflect(ptr, length);
+    crossSource = reflect.compile();
+    BuildManifest(reflect, [](const spirv_cross::Resource&, RawBinding&) {});
+  } catch (const spirv_cross::CompilerError& e) {
+    printf("Reflection Export Failed!\n%s\n", e.what());
+    return nullptr;
+  }
+  return crossSource.c_str();
+}
+
+// Bindings of the last successful decompile
+extern "C" const RawBinding* GetBindingManifest(std::size_t* count) {
+  *count = manifest.size();
+  return manifest.data();
+}
+
+// Combined samplers and flattened blocks of the last GLSL decompile
+extern "C" const RawGlesCompat* GetGlesCompat() {
+  glesCompat.samplers = compatSamplers.data();
+  glesCompat.samplerCount = compatSamplers.size();
+  glesCompat.blocks = compatBlocks.data();
+  glesCompat.blockCount = compatBlocks.size();
+  glesCompat.members = compatMembers.data();
+  glesCompat.memberCount = compatMembers.size();
+  return &glesCompat;
+}
@@ -0,0 +1,114 @@
+#pragma once
+#include <cstdint>
+#include <string>
+
+#include "spirv.h"
+
+// Per-target SPIRV-Cross options, mirrored by the structs in src/cross.rs
+
+struct GlslOptions {
+  uint32_t version;
+  bool es;
+  bool vulkanSemantics;
+  bool flipVertY;
+  // Combine separate images and samplers and flatten UBOs into uniform
+  // arrays, for GLES 2.0 and WebGL 1.0
+  bool glesCompat;
+};
+
+struct HlslOptions {
+  uint32_t shaderModel;
+  bool pointSizeCompat;
+};
+
+// Remap of one (set, binding) pair to target specific slots. HLSL has a
+// register for each class, the resource type picks which one is used
+struct BindingSlot {
+  uint32_t set;
+  uint32_t binding;
+  uint32_t hlslCbv;
+  uint32_t hlslSrv;
+  uint32_t hlslUav;
+  uint32_t hlslSampler;
+  uint32_t hlslSpace;
+  uint32_t mslBuffer;
+  uint32_t mslTexture;
+  uint32_t mslSampler;
+};
+
+// Where a resource ended up in the cross-compiled source. For HLSL space is
+// the register space, sampler is the sampler index (MSL) or s register (HLSL)
+// of a combined image sampler
+struct RawBinding {
+  int kind;
+  const char* name;
+  uint32_t set;
+  uint32_t binding;
+  uint32_t slot;
+  uint32_t space;
+  uint32_t sampler;
+  bool remapped;
+};
+
+// Sampler2D made from an image and sampler pair by the GLES compatibility
+// mode
+struct RawCombinedSampler {
+  const char* name;
+  const char* image;
+  const char* sampler;
+};
+
+// A UBO flattened into `uniform vec4 array[vectors]`, its members follow in
+// the member array
+struct RawFlattenedBlock {
+  const char* block;
+  const char* array;
+  uint32_t vectors;
+  std::size_t firstMember;
+  std::size_t memberCount;
+};
+
+// Position of a block member in the flattened array, as the vec4 element and
+// the component it starts at
+struct RawFlattenedMember {
+  const char* name;
+  uint32_t offset;
+  uint32_t element;
+  uint32_t component;
+};
+
+struct RawGlesCompat {
+  const RawCombinedSampler* samplers;
+  std::size_t samplerCount;
+  const RawFlattenedBlock* blocks;
+  std::size_t blockCount;
+  const RawFlattenedMember* members;
+  std::size_t memberCount;
+};
+
+struct MslOptions {
+  uint32_t major;
+  uint32_t minor;
+  uint32_t patch;
+  int platform;
+  bool argumentBuffers;
+};
+
+extern "C" {
+const char* DecompileToGLSL(unsigned int* ptr,
+                            std::size_t length,
+                            const GlslOptions* options);
+const char* DecompileToHLSL(unsigned int* ptr,
+                            std::size_t length,
+                            const HlslOptions* options,
+                            const BindingSlot* remaps,
+                            std::size_t remapCount);
+const char* DecompileToMetal(unsigned int* ptr,
+                             std::size_t length,
+                             const MslOptions* options,
+                             const BindingSlot* remaps,
+                             std::size_t remapCount);
+const char* DecompileToReflection(unsigned int* ptr, std::size_t length);
+const RawBinding* GetBindingManifest(std::size_t* count);
+const RawGlesCompat* GetGlesCompat();
+}
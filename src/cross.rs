@@ -0,0 +1,685 @@
+// Per-target SPIRV-Cross options, laid out to match src/cross.h
+
+use crate::reflect::{name, slice, ResourceKind};
+use serde::Serialize;
+use std::fs;
+use std::io;
+use std::os::raw::c_char;
+use std::path::Path;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct GlslOptions {
+    pub version: u32,
+    pub es: bool,
+    pub vulkan_semantics: bool,
+    pub flip_vert_y: bool,
+    /// Combines separate images and samplers and flattens uniform blocks into
+    /// vec4 arrays, see `BindingManifest` for the new names
+    pub gles_compat: bool,
+}
+
+impl Default for GlslOptions {
+    fn default() -> Self {
+        GlslOptions {
+            version: 450,
+            es: false,
+            vulkan_semantics: false,
+            flip_vert_y: false,
+            gles_compat: false,
+        }
+    }
+}
+
+impl GlslOptions {
+    pub fn gles30() -> Self {
+        GlslOptions {
+            version: 300,
+            es: true,
+            ..Default::default()
+        }
+    }
+
+    /// GLSL ES 1.00 for GLES 2.0 and WebGL 1.0
+    pub fn webgl() -> Self {
+        GlslOptions {
+            version: 100,
+            es: true,
+            gles_compat: true,
+            ..Default::default()
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct HlslOptions {
+    /// 50 for SM 5.0, 51 for SM 5.1 and so on
+    pub shader_model: u32,
+    pub point_size_compat: bool,
+}
+
+impl Default for HlslOptions {
+    fn default() -> Self {
+        HlslOptions {
+            shader_model: 50,
+            point_size_compat: false,
+        }
+    }
+}
+
+// Values match spirv_cross::CompilerMSL::Options::Platform
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum MslPlatform {
+    Ios = 0,
+    MacOS = 1,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct MslOptions {
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
+    pub platform: MslPlatform,
+    pub argument_buffers: bool,
+}
+
+impl Default for MslOptions {
+    /// Metal 2.0 on macOS
+    fn default() -> Self {
+        MslOptions {
+            major: 2,
+            minor: 0,
+            patch: 0,
+            platform: MslPlatform::MacOS,
+            argument_buffers: false,
+        }
+    }
+}
+
+/// HLSL register class, picked by SPIRV-Cross from the resource type
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum HlslClass {
+    /// `b` registers of uniform buffers
+    Cbv,
+    /// `t` registers of textures and read only storage buffers
+    Srv,
+    /// `u` registers of storage images and writable storage buffers
+    Uav,
+    /// `s` registers, including the sampler half of a combined image sampler
+    Sampler,
+}
+
+/// Target slots for one (set, binding) pair. HLSL emits
+/// `register(<class><register>, space<hlsl_space>)` with the register of the
+/// class the resource falls in, MSL picks the buffer, texture or sampler index
+/// by the resource type.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct BindingSlot {
+    pub set: u32,
+    pub binding: u32,
+    pub hlsl_cbv: u32,
+    pub hlsl_srv: u32,
+    pub hlsl_uav: u32,
+    pub hlsl_sampler: u32,
+    /// Only emitted from SM 5.1
+    pub hlsl_space: u32,
+    pub msl_buffer: u32,
+    pub msl_texture: u32,
+    pub msl_sampler: u32,
+}
+
+impl BindingSlot {
+    /// Keeps the Vulkan numbering, the binding as the register and index and
+    /// the set as the register space
+    pub fn identity(set: u32, binding: u32) -> Self {
+        BindingSlot {
+            set,
+            binding,
+            hlsl_cbv: binding,
+            hlsl_srv: binding,
+            hlsl_uav: binding,
+            hlsl_sampler: binding,
+            hlsl_space: set,
+            msl_buffer: binding,
+            msl_texture: binding,
+            msl_sampler: binding,
+        }
+    }
+
+    pub fn hlsl_register(&self, class: HlslClass) -> u32 {
+        match class {
+            HlslClass::Cbv => self.hlsl_cbv,
+            HlslClass::Srv => self.hlsl_srv,
+            HlslClass::Uav => self.hlsl_uav,
+            HlslClass::Sampler => self.hlsl_sampler,
+        }
+    }
+}
+
+/// Table of (set, binding) remaps applied when cross-compiling. Pairs
+/// without an entry keep the numbering SPIRV-Cross picks for them.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct BindingRemap {
+    slots: Vec<BindingSlot>,
+}
+
+impl BindingRemap {
+    pub fn new() -> Self {
+        BindingRemap::default()
+    }
+
+    /// Replaces any earlier entry for the same (set, binding)
+    pub fn insert(&mut self, slot: BindingSlot) -> &mut Self {
+        match self.slot_mut(slot.set, slot.binding) {
+            Some(existing) => *existing = slot,
+            None => self.slots.push(slot),
+        }
+        self
+    }
+
+    /// Gives every register class of the pair `register`
+    pub fn hlsl(&mut self, set: u32, binding: u32, register: u32, space: u32) -> &mut Self {
+        let mut slot = self
+            .get(set, binding)
+            .unwrap_or(BindingSlot::identity(set, binding));
+        slot.hlsl_cbv = register;
+        slot.hlsl_srv = register;
+        slot.hlsl_uav = register;
+        slot.hlsl_sampler = register;
+        slot.hlsl_space = space;
+        self.insert(slot)
+    }
+
+    /// Register of one class only, such as the `s` register of a combined
+    /// image sampler whose texture keeps its `t` register
+    pub fn hlsl_class(
+        &mut self,
+        set: u32,
+        binding: u32,
+        class: HlslClass,
+        register: u32,
+    ) -> &mut Self {
+        let mut slot = self
+            .get(set, binding)
+            .unwrap_or(BindingSlot::identity(set, binding));
+        match class {
+            HlslClass::Cbv => slot.hlsl_cbv = register,
+            HlslClass::Srv => slot.hlsl_srv = register,
+            HlslClass::Uav => slot.hlsl_uav = register,
+            HlslClass::Sampler => slot.hlsl_sampler = register,
+        }
+        self.insert(slot)
+    }
+
+    pub fn msl(
+        &mut self,
+        set: u32,
+        binding: u32,
+        buffer: u32,
+        texture: u32,
+        sampler: u32,
+    ) -> &mut Self {
+        let mut slot = self
+            .get(set, binding)
+            .unwrap_or(BindingSlot::identity(set, binding));
+        slot.msl_buffer = buffer;
+        slot.msl_texture = texture;
+        slot.msl_sampler = sampler;
+        self.insert(slot)
+    }
+
+    pub fn get(&self, set: u32, binding: u32) -> Option<BindingSlot> {
+        self.slots
+            .iter()
+            .find(|s| s.set == set && s.binding == binding)
+            .copied()
+    }
+
+    pub fn slots(&self) -> &[BindingSlot] {
+        &self.slots
+    }
+
+    fn slot_mut(&mut self, set: u32, binding: u32) -> Option<&mut BindingSlot> {
+        self.slots
+            .iter_mut()
+            .find(|s| s.set == set && s.binding == binding)
+    }
+}
+
+#[repr(C)]
+pub(crate) struct RawBinding {
+    kind: i32,
+    name: *const c_char,
+    set: u32,
+    binding: u32,
+    slot: u32,
+    space: u32,
+    sampler: u32,
+    remapped: bool,
+}
+
+/// Where one resource ended up in the cross-compiled source
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct BindingEntry {
+    pub name: String,
+    pub kind: ResourceKind,
+    pub set: u32,
+    pub binding: u32,
+    /// Register for HLSL, buffer or texture index for MSL, binding for GLSL
+    pub slot: u32,
+    /// HLSL register space, 0 below SM 5.1 which has no spaces
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub space: Option<u32>,
+    /// MSL sampler index or HLSL `s` register of a combined image sampler
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sampler: Option<u32>,
+    /// Whether the slot came from the remap table
+    pub remapped: bool,
+}
+
+#[repr(C)]
+struct RawCombinedSampler {
+    name: *const c_char,
+    image: *const c_char,
+    sampler: *const c_char,
+}
+
+#[repr(C)]
+struct RawFlattenedBlock {
+    block: *const c_char,
+    array: *const c_char,
+    vectors: u32,
+    first_member: usize,
+    member_count: usize,
+}
+
+#[repr(C)]
+struct RawFlattenedMember {
+    name: *const c_char,
+    offset: u32,
+    element: u32,
+    component: u32,
+}
+
+#[repr(C)]
+pub(crate) struct RawGlesCompat {
+    samplers: *const RawCombinedSampler,
+    sampler_count: usize,
+    blocks: *const RawFlattenedBlock,
+    block_count: usize,
+    members: *const RawFlattenedMember,
+    member_count: usize,
+}
+
+/// A sampler2D the GLES compatibility mode made from an image and a sampler
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct CombinedSampler {
+    pub name: String,
+    pub image: String,
+    pub sampler: String,
+}
+
+/// A uniform block flattened into `uniform vec4 <array>[<vectors>]`
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct FlattenedBlock {
+    pub block: String,
+    pub array: String,
+    pub vectors: u32,
+    pub members: Vec<FlattenedMember>,
+}
+
+/// Where a block member lives in the flattened array
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct FlattenedMember {
+    pub name: String,
+    pub offset: u32,
+    /// vec4 element the member starts in
+    pub element: u32,
+    /// Component of that element, 0 to 3
+    pub component: u32,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct BindingManifest {
+    pub target: String,
+    pub bindings: Vec<BindingEntry>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub combined_samplers: Vec<CombinedSampler>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub flattened_blocks: Vec<FlattenedBlock>,
+}
+
+impl BindingManifest {
+    /// Entry of the resource with the given name
+    pub fn get(&self, name: &str) -> Option<&BindingEntry> {
+        self.bindings.iter().find(|b| b.name == name)
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap()
+    }
+
+    pub(crate) unsafe fn collect(target: &str, raw: *const RawBinding, count: usize) -> Self {
+        let mut bindings = Vec::new();
+        for raw in slice(raw, count) {
+            bindings.push(BindingEntry {
+                name: name(raw.name),
+                kind: ResourceKind::from_raw(raw.kind),
+                set: raw.set,
+                binding: raw.binding,
+                slot: raw.slot,
+                space: if target == "hlsl" {
+                    Some(raw.space)
+                } else {
+                    None
+                },
+                sampler: if (target == "hlsl" || target == "msl") && raw.sampler != u32::MAX {
+                    Some(raw.sampler)
+                } else {
+                    None
+                },
+                remapped: raw.remapped,
+            });
+        }
+        BindingManifest {
+            target: target.to_string(),
+            bindings,
+            ..Default::default()
+        }
+    }
+
+    pub(crate) unsafe fn add_gles_compat(&mut self, raw: &RawGlesCompat) {
+        for sampler in slice(raw.samplers, raw.sampler_count) {
+            self.combined_samplers.push(CombinedSampler {
+                name: name(sampler.name),
+                image: name(sampler.image),
+                sampler: name(sampler.sampler),
+            });
+        }
+
+        let members = slice(raw.members, raw.member_count);
+        for block in slice(raw.blocks, raw.block_count) {
+            let range = block.first_member..block.first_member + block.member_count;
+            self.flattened_blocks.push(FlattenedBlock {
+                block: name(block.block),
+                array: name(block.array),
+                vectors: block.vectors,
+                members: members[range]
+                    .iter()
+                    .map(|m| FlattenedMember {
+                        name: name(m.name),
+                        offset: m.offset,
+                        element: m.element,
+                        component: m.component,
+                    })
+                    .collect(),
+            });
+        }
+    }
+}
+
+/// Cross-compiled source together with the bindings it was given
+#[derive(Clone, Debug, PartialEq)]
+pub struct CrossCompiled {
+    pub source: String,
+    pub manifest: BindingManifest,
+}
+
+impl CrossCompiled {
+    /// Writes the source to `path` and the manifest next to it as
+    /// `<path>.bindings.json`
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let path = path.as_ref();
+        fs::write(path, &self.source)?;
+        let mut manifest = path.as_os_str().to_owned();
+        manifest.push(".bindings.json");
+        fs::write(manifest, self.manifest.to_json())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::limits::ResourceLimits;
+    use crate::module::SpirvModule;
+    use crate::{GraphicsAPI, ShaderCompiler, Stage};
+    use std::ffi::CString;
+
+    const FRAGMENT: &str = "#version 450
+layout(set = 0, binding = 0) uniform Frame {
+    vec4 tint;
+} frame;
+layout(set = 1, binding = 2) uniform sampler2D albedo;
+layout(set = 1, binding = 3) readonly buffer Weights {
+    vec4 weights[];
+} weights;
+layout(set = 1, binding = 4) buffer Counters {
+    uint counters[];
+} counters;
+layout(location = 0) in vec2 uv;
+layout(location = 0) out vec4 colour;
+void main() {
+    counters.counters[0] += 1u;
+    colour = frame.tint * texture(albedo, uv) * weights.weights[0];
+}
+";
+
+    fn fragment() -> (ShaderCompiler, SpirvModule) {
+        let mut compiler = ShaderCompiler::new(&ResourceLimits::default());
+        let module = compiler.compile_stage(Stage::FragmentStage, FRAGMENT);
+        (compiler, module)
+    }
+
+    fn hlsl(shader_model: u32, remap: &BindingRemap) -> CrossCompiled {
+        let (compiler, module) = fragment();
+        let options = HlslOptions {
+            shader_model,
+            ..Default::default()
+        };
+        compiler
+            .decompile_spirv(&module, &GraphicsAPI::HLSL(options), remap)
+            .unwrap()
+    }
+
+    #[test]
+    fn remap_classes_are_set_separately() {
+        let mut remap = BindingRemap::new();
+        remap
+            .hlsl(1, 2, 4, 3)
+            .hlsl_class(1, 2, HlslClass::Sampler, 9)
+            .msl(1, 2, 0, 1, 2);
+        let slot = remap.get(1, 2).unwrap();
+        assert_eq!(slot.hlsl_register(HlslClass::Srv), 4);
+        assert_eq!(slot.hlsl_register(HlslClass::Cbv), 4);
+        assert_eq!(slot.hlsl_register(HlslClass::Sampler), 9);
+        assert_eq!(slot.hlsl_space, 3);
+        assert_eq!((slot.msl_texture, slot.msl_sampler), (1, 2));
+        assert_eq!(remap.slots().len(), 1);
+
+        let slot = BindingSlot::identity(2, 5);
+        assert_eq!(slot.hlsl_register(HlslClass::Uav), 5);
+        assert_eq!(slot.hlsl_space, 2);
+        assert_eq!(remap.get(2, 5), None);
+    }
+
+    #[test]
+    fn manifest_json_keeps_target_fields() {
+        let name = CString::new("albedo").unwrap();
+        let raw = [RawBinding {
+            kind: 2,
+            name: name.as_ptr(),
+            set: 1,
+            binding: 2,
+            slot: 4,
+            space: 3,
+            sampler: 9,
+            remapped: true,
+        }];
+        let manifest = unsafe { BindingManifest::collect("hlsl", raw.as_ptr(), raw.len()) };
+        let albedo = manifest.get("albedo").unwrap();
+        assert_eq!(albedo.kind, ResourceKind::SampledImage);
+        assert_eq!(
+            (albedo.slot, albedo.space, albedo.sampler),
+            (4, Some(3), Some(9))
+        );
+        assert!(manifest.to_json().contains("\"space\": 3"));
+
+        let manifest = unsafe { BindingManifest::collect("glsl", raw.as_ptr(), raw.len()) };
+        let json = manifest.to_json();
+        assert!(json.contains("\"target\": \"glsl\""));
+        assert!(!json.contains("space"));
+        assert!(!json.contains("sampler"));
+    }
+
+    #[test]
+    fn hlsl_remap_follows_the_register_class() {
+        let mut remap = BindingRemap::new();
+        remap
+            .hlsl_class(1, 2, HlslClass::Srv, 5)
+            .hlsl_class(1, 2, HlslClass::Sampler, 7)
+            .hlsl(1, 3, 6, 2)
+            .hlsl(1, 4, 1, 2);
+        let compiled = hlsl(51, &remap);
+        let manifest = &compiled.manifest;
+        assert!(compiled.source.contains("register(t5, space1)"));
+        assert!(compiled.source.contains("register(s7, space1)"));
+
+        let albedo = manifest.get("albedo").unwrap();
+        assert_eq!((albedo.slot, albedo.sampler), (5, Some(7)));
+        assert!(albedo.remapped);
+        // Read only buffers are t registers, writable ones u
+        assert!(compiled.source.contains("register(t6, space2)"));
+        assert!(compiled.source.contains("register(u1, space2)"));
+        assert_eq!(manifest.get("Weights").unwrap().slot, 6);
+        assert_eq!(manifest.get("Counters").unwrap().space, Some(2));
+
+        let frame = manifest.get("Frame").unwrap();
+        assert_eq!(
+            (frame.slot, frame.space, frame.remapped),
+            (0, Some(0), false)
+        );
+    }
+
+    #[test]
+    fn spaces_need_shader_model_5_1() {
+        let mut remap = BindingRemap::new();
+        remap.hlsl(1, 2, 5, 3);
+        let compiled = hlsl(50, &remap);
+        assert!(!compiled.source.contains("space"));
+        for binding in &compiled.manifest.bindings {
+            assert_eq!(binding.space, Some(0), "{}", binding.name);
+        }
+        assert_eq!(compiled.manifest.get("albedo").unwrap().slot, 5);
+    }
+
+    #[test]
+    fn reflection_json_describes_the_module() {
+        let (compiler, module) = fragment();
+        let compiled = compiler
+            .decompile_spirv(&module, &GraphicsAPI::Reflection, &BindingRemap::new())
+            .unwrap();
+        let json: serde_json::Value = serde_json::from_str(&compiled.source).unwrap();
+        assert_eq!(json["entryPoints"][0]["name"], "main");
+        assert_eq!(json["entryPoints"][0]["mode"], "frag");
+
+        let ubo = &json["ubos"][0];
+        assert_eq!(ubo["name"], "Frame");
+        assert_eq!(
+            (ubo["set"].as_u64(), ubo["binding"].as_u64()),
+            (Some(0), Some(0))
+        );
+        assert_eq!(json["textures"][0]["name"], "albedo");
+        assert_eq!(json["textures"][0]["binding"], 2);
+        assert_eq!(json["ssbos"].as_array().map(|a| a.len()), Some(2));
+
+        // The manifest keeps the Vulkan numbering
+        assert_eq!(compiled.manifest.target, "json");
+        let albedo = compiled.manifest.get("albedo").unwrap();
+        assert_eq!((albedo.set, albedo.slot, albedo.space), (1, 2, None));
+    }
+
+    const SEPARATE: &str = "#version 450
+layout(set = 0, binding = 0) uniform Frame {
+    vec4 tint;
+    float exposure;
+    vec2 scale;
+} frame;
+layout(set = 0, binding = 1) uniform texture2D albedoImage;
+layout(set = 0, binding = 2) uniform sampler linearSampler;
+layout(location = 0) in vec2 uv;
+layout(location = 0) out vec4 colour;
+void main() {
+    vec4 texel = texture(sampler2D(albedoImage, linearSampler), uv * frame.scale);
+    colour = frame.tint * texel * frame.exposure;
+}
+";
+
+    #[test]
+    fn gles_compat_reports_combined_samplers_and_flattened_blocks() {
+        let mut compiler = ShaderCompiler::new(&ResourceLimits::default());
+        let module = compiler.compile_stage(Stage::FragmentStage, SEPARATE);
+        let compiled = compiler
+            .decompile_spirv(
+                &module,
+                &GraphicsAPI::GLSL(GlslOptions::webgl()),
+                &BindingRemap::new(),
+            )
+            .unwrap();
+        let manifest = &compiled.manifest;
+
+        let combined = "SPIRV_Cross_CombinedalbedoImagelinearSampler";
+        assert_eq!(
+            manifest.combined_samplers,
+            [CombinedSampler {
+                name: combined.to_string(),
+                image: "albedoImage".to_string(),
+                sampler: "linearSampler".to_string(),
+            }]
+        );
+        assert!(compiled
+            .source
+            .contains(&format!("sampler2D {};", combined)));
+
+        assert_eq!(manifest.flattened_blocks.len(), 1);
+        let block = &manifest.flattened_blocks[0];
+        assert_eq!(
+            (block.block.as_str(), block.array.as_str()),
+            ("Frame", "Frame")
+        );
+        assert_eq!(block.vectors, 2);
+        assert!(compiled.source.contains("uniform vec4 Frame[2];"));
+        let members: Vec<(&str, u32, u32, u32)> = block
+            .members
+            .iter()
+            .map(|m| (m.name.as_str(), m.offset, m.element, m.component))
+            .collect();
+        assert_eq!(
+            members,
+            [
+                ("tint", 0, 0, 0),
+                ("exposure", 16, 1, 0),
+                ("scale", 24, 1, 2)
+            ]
+        );
+        assert!(manifest.to_json().contains("\"flattened_blocks\""));
+    }
+
+    #[test]
+    fn plain_glsl_has_no_gles_mapping() {
+        let (compiler, module) = fragment();
+        let compiled = compiler
+            .decompile_spirv(
+                &module,
+                &GraphicsAPI::GLSL(GlslOptions::default()),
+                &BindingRemap::new(),
+            )
+            .unwrap();
+        assert!(compiled.manifest.combined_samplers.is_empty());
+        assert!(compiled.manifest.flattened_blocks.is_empty());
+        assert!(!compiled.manifest.to_json().contains("flattened_blocks"));
+    }
+}
@@ -0,0 +1,63 @@
+#pragma once
+#include <algorithm>
+#include <map>
+#include <string>
+#include <vector>
+
+#include "DirStackFileIncluder.h"
+
+// Resolves #include against in-memory files first, then the directory of the
+// including file and the registered include directories. <> and "" includes
+// search the same places. Every file found is recorded in `included`.
+class ShaderIncluder : public DirStackFileIncluder {
+ public:
+  ShaderIncluder(const std::vector<std::string>& dirs,
+                 const std::map<std::string, std::string>& files,
+                 std::vector<std::string>& included)
+      : virtualFiles(files), includedFiles(included) {
+    for (auto& dir : dirs)
+      pushExternalLocalDirectory(dir);
+  }
+
+  IncludeResult* includeLocal(const char* headerName,
+                              const char* includerName,
+                              size_t inclusionDepth) override {
+    return Resolve(headerName, includerName, inclusionDepth);
+  }
+
+  IncludeResult* includeSystem(const char* headerName,
+                               const char* includerName,
+                               size_t inclusionDepth) override {
+    return Resolve(headerName, includerName, inclusionDepth);
+  }
+
+ private:
+  const std::map<std::string, std::string>& virtualFiles;
+  std::vector<std::string>& includedFiles;
+
+  IncludeResult* Resolve(const char* headerName,
+                         const char* includerName,
+                         size_t inclusionDepth) {
+    IncludeResult* result = nullptr;
+    auto file = virtualFiles.find(headerName);
+    if (file != virtualFiles.end()) {
+      // Keep the directory stack in step with readLocalPath, a file included
+      // from a virtual one resolves against the virtual file's includer
+      directoryStack.resize(inclusionDepth + externalLocalDirectoryCount);
+      if (inclusionDepth == 1)
+        directoryStack.back() = getDirectory(includerName);
+      directoryStack.push_back(directoryStack.back());
+      // No user data, so releaseInclude leaves the map's string alone
+      result = new IncludeResult(file->first, file->second.data(),
+                                 file->second.size(), nullptr);
+    } else {
+      result = readLocalPath(headerName, includerName, (int)inclusionDepth);
+    }
+
+    if (result && std::find(includedFiles.begin(), includedFiles.end(),
+                            result->headerName) == includedFiles.end()) {
+      includedFiles.push_back(result->headerName);
+    }
+    return result;
+  }
+};
@@ -48,9 +48,15 @@ void AddKeyword(uint64_t hash, const char* keyword) {
     }
   }
   shaderHashes.hashes.push_back(hash);
-  preamble.append("#define ");
-  preamble.append(keyword);
-  preamble.append("\n");
+
+  // Valued keywords are NAME=VALUE, hashed whole but defined as NAME VALUE
+  std::string define(keyword);
+  std::size_t equals = define.find('=');
+  if (equals != std::string::npos)
+    define[equals] = ' ';
+  keywordPreamble.append("#define ");
+  keywordPreamble.append(define);
+  keywordPreamble.append("\n");
 }
 
 void AddKeyword(uint64_t hash, const std::string& keyword) {
@@ -61,41 +67,38 @@ bool DoReserveKeyword(const std::string& newkw,
                       uint64_t hash,
                       bool updateLocal = false) {
   std::lock_guard<std::mutex> guard(kw_mutex);
-  bool found = false;
-  for (auto& kw : keywords) {
-    // Check the hash
-    if (kw.hash == hash) {
-      // Now check the string itself, if they are different show a collision
-      // error
-      if (kw.keyword != newkw) {
-        std::cout << "COLLISION! "
-                  << "'" << newkw << "' collides with hash 0x" << std::hex
-                  << hash << std::dec << " for '" << kw.keyword << "'"
-                  << std::endl;
-        return false;
-      }
-      found = true;
+  auto bit = keywordBits.find(hash);
+  if (bit != keywordBits.end()) {
+    // Now check the string itself, if they are different show a collision
+    // error
+    const Keyword& kw = keywords[bit->second];
+    if (kw.keyword != newkw) {
+      std::cout << "COLLISION! "
+                << "'" << newkw << "' collides with hash 0x" << std::hex
+                << hash << std::dec << " for '" << kw.keyword << "'"
+                << std::endl;
+      return false;
     }
-    if (found)
-      break;
+  } else {
+    if (keywords.size() >= MaxKeywords) {
+      std::cout << "Cannot reserve '" << newkw << "', the registry holds at "
+                << "most " << MaxKeywords << " keywords" << std::endl;
+      return false;
+    }
+    keywordBits.emplace(hash, static_cast<int>(keywords.size()));
+    keywords.push_back(Keyword{newkw, hash});
   }
 
-  if (!found) {
-    keywords.push_back(Keyword{newkw, hash});
-    if (updateLocal)
-      threadKeywords.push_back(Keyword{newkw, hash});
-  } else {
-    if (updateLocal) {
-      found = false;
-      for (auto& kw : threadKeywords) {
-        if (kw.hash == hash) {
-          found = true;
-          break;
-        }
+  if (updateLocal) {
+    bool found = false;
+    for (auto& kw : threadKeywords) {
+      if (kw.hash == hash) {
+        found = true;
+        break;
       }
-      if (!found)
-        threadKeywords.push_back(Keyword{newkw, hash});
     }
+    if (!found)
+      threadKeywords.push_back(Keyword{newkw, hash});
   }
   return true;
 }
@@ -135,92 +138,136 @@ uint64_t ReserveKeyword(const char* keyword) {
   return 0;
 }
 
+int32_t KeywordBitFromHash(uint64_t hash) {
+  auto cached = threadKeywordBits.find(hash);
+  if (cached != threadKeywordBits.end())
+    return cached->second;
+
+  std::lock_guard<std::mutex> guard(kw_mutex);
+  auto bit = keywordBits.find(hash);
+  if (bit == keywordBits.end())
+    return -1;
+  threadKeywordBits.emplace(hash, bit->second);
+  return bit->second;
+}
+
+int32_t KeywordBit(const char* keyword) {
+  return KeywordBitFromHash(Hash(keyword));
+}
+
+uint64_t KeywordHashFromBit(int32_t bit) {
+  std::lock_guard<std::mutex> guard(kw_mutex);
+  if (bit < 0 || static_cast<std::size_t>(bit) >= keywords.size())
+    return 0;
+  return keywords[bit].hash;
+}
+
 bool EnableKeyword(const char* keyword) {
-  uint64_t hash = FindKeyword(keyword);
-  if (hash) {
-    for (auto& kw : threadEnabledKeywords) {
-      if (kw.hash == hash)
-        return true;
-    }
-    threadEnabledKeywords.push_back(Keyword{keyword, hash});
-    return true;
-  }
-  return false;
+  int32_t bit = KeywordBit(keyword);
+  if (bit < 0)
+    return false;
+  threadEnabledKeywords.Set(bit);
+  return true;
 }
 
 bool EnableGlobalKeyword(const char* keyword) {
-  uint64_t hash = FindKeyword(keyword);
-  if (hash) {
-    std::lock_guard<std::mutex> guard(enabled_mutex);
-    for (auto& kw : enabledKeywords) {
-      if (kw.hash == hash) {
-        return true;
-      }
-    }
-    enabledKeywords.push_back(Keyword{keyword, hash});
-    keywordsEnabled = true;
-    return true;
-  }
-  return false;
+  int32_t bit = KeywordBit(keyword);
+  if (bit < 0)
+    return false;
+  enabledKeywords[bit >> 6].fetch_or(uint64_t(1) << (bit & 63));
+  return true;
 }
 
 bool DisableKeyword(const char* keyword) {
-  uint64_t hash = FindKeyword(keyword);
-  if (hash) {
-    bool found = false;
-    if (!threadEnabledKeywords.empty()) {
-      for (auto& kw : threadEnabledKeywords) {
-        if (kw.hash == hash) {
-          found = true;
-          break;
-        }
-      }
-
-      if (found) {
-        std::vector<Keyword>::iterator it = threadEnabledKeywords.begin();
-        while (it != threadEnabledKeywords.end()) {
-          if (it->hash == hash)
-            it = threadEnabledKeywords.erase(it);
-          else
-            it++;
-        }
-        return true;
-      }
-    }
+  int32_t bit = KeywordBit(keyword);
+  if (bit < 0 || !threadEnabledKeywords.Test(bit))
     return false;
-  }
-  return false;
+  threadEnabledKeywords.Clear(bit);
+  return true;
 }
 
 bool DisableGlobalKeyword(const char* keyword) {
-  uint64_t hash = FindKeyword(keyword);
-  if (hash) {
-    bool found = false;
-    if (keywordsEnabled) {
-      std::lock_guard<std::mutex> guard(enabled_mutex);
-      for (auto& kw : enabledKeywords) {
-        if (kw.hash == hash) {
-          found = true;
-          break;
-        }
-      }
+  int32_t bit = KeywordBit(keyword);
+  if (bit < 0)
+    return false;
+  uint64_t flag = uint64_t(1) << (bit & 63);
+  return enabledKeywords[bit >> 6].fetch_and(~flag) & flag;
+}
 
-      if (found) {
-        std::vector<Keyword>::iterator it = enabledKeywords.begin();
-        while (it != enabledKeywords.end()) {
-          if (it->hash == hash)
-            it = enabledKeywords.erase(it);
-          else
-            it++;
-        }
-        if (enabledKeywords.empty())
-          keywordsEnabled = false;
-        return true;
+void GetEnabledKeywords(KeywordMask* mask) {
+  *mask = threadEnabledKeywords;
+}
+
+void SetEnabledKeywords(const KeywordMask* mask) {
+  threadEnabledKeywords = *mask;
+}
+
+void GetGlobalEnabledKeywords(KeywordMask* mask) {
+  for (int i = 0; i < MaxKeywords / 64; ++i)
+    mask->bits[i] = enabledKeywords[i].load();
+}
+
+void SetGlobalEnabledKeywords(const KeywordMask* mask) {
+  for (int i = 0; i < MaxKeywords / 64; ++i)
+    enabledKeywords[i].store(mask->bits[i]);
+}
+
+// The Enable functions return the earlier set, so callers can tell which
+// keywords they turned on and turn off only those
+void EnableKeywords(const KeywordMask* mask, KeywordMask* previous) {
+  *previous = threadEnabledKeywords;
+  for (int i = 0; i < MaxKeywords / 64; ++i)
+    threadEnabledKeywords.bits[i] |= mask->bits[i];
+}
+
+void DisableKeywords(const KeywordMask* mask) {
+  for (int i = 0; i < MaxKeywords / 64; ++i)
+    threadEnabledKeywords.bits[i] &= ~mask->bits[i];
+}
+
+void EnableGlobalKeywords(const KeywordMask* mask, KeywordMask* previous) {
+  for (int i = 0; i < MaxKeywords / 64; ++i)
+    previous->bits[i] = enabledKeywords[i].fetch_or(mask->bits[i]);
+}
+
+void DisableGlobalKeywords(const KeywordMask* mask) {
+  for (int i = 0; i < MaxKeywords / 64; ++i)
+    enabledKeywords[i].fetch_and(~mask->bits[i]);
+}
+
+// Adds the thread's and the global enabled keywords to the program being
+// compiled
+void AddEnabledKeywords() {
+  KeywordMask enabled = threadEnabledKeywords;
+  bool any = false;
+  for (int i = 0; i < MaxKeywords / 64; ++i) {
+    enabled.bits[i] |= enabledKeywords[i].load();
+    any |= enabled.bits[i] != 0;
+  }
+  if (!any)
+    return;
+
+  std::vector<Keyword> found;
+  {
+    std::lock_guard<std::mutex> guard(kw_mutex);
+    for (std::size_t bit = 0; bit < keywords.size(); ++bit) {
+      if (enabled.Test(static_cast<int>(bit)))
+        found.push_back(keywords[bit]);
+    }
+  }
+  for (auto& kw : found) {
+    // GetKeywordsFromID names the combination's hashes from threadKeywords
+    bool known = false;
+    for (auto& local : threadKeywords) {
+      if (local.hash == kw.hash) {
+        known = true;
+        break;
       }
     }
-    return false;
+    if (!known)
+      threadKeywords.push_back(kw);
+    AddKeyword(kw.hash, kw.keyword);
   }
-  return false;
 }
 
 uint64_t AddKeyword(const char* keyword) {
@@ -251,7 +298,8 @@ uint64_t AddKeyword(const char* keyword) {
     }
   }
 
-  DoReserveKeyword(newkw, hash, true);
+  if (!DoReserveKeyword(newkw, hash, true))
+    return 0;
   AddKeyword(hash, keyword);
   return hash;
 }
@@ -277,24 +325,47 @@ const char* PrintKeywords() {
   return queryIDResult.c_str();
 }
 
+// Snapshot of the registry for Rust, the copies keep the names valid if
+// another thread registers more keywords
+thread_local std::vector<Keyword> listedKeywords;
+thread_local std::vector<RawKeyword> rawKeywords;
+
+const RawKeyword* ListKeywords(std::size_t* count) {
+  {
+    std::lock_guard<std::mutex> guard(kw_mutex);
+    listedKeywords = keywords;
+  }
+  rawKeywords.clear();
+  for (auto& kw : listedKeywords)
+    rawKeywords.push_back(RawKeyword{kw.keyword.c_str(), kw.hash});
+  *count = rawKeywords.size();
+  return rawKeywords.data();
+}
+
+// False when another keyword list already has this combination's hash, a
+// list of a different length included
 bool AuthenticateKeywords() {
   for (auto& kwc : threadKeywordCombos) {
-    if (kwc.hash == shaderHashes.hash) {
-      if (kwc.hashes.size() == shaderHashes.hashes.size()) {
-        for (std::size_t i = 0; i < shaderHashes.hashes.size(); ++i) {
-          if (kwc.hashes[i] != shaderHashes.hashes[i]) {
-            return false;
-          }
-        }
-        return true;
-      }
-    }
+    if (kwc.hash == shaderHashes.hash)
+      return kwc.hashes == shaderHashes.hashes;
   }
 
   threadKeywordCombos.push_back(shaderHashes);
   return true;
 }
 
+// Authenticates a combination as CompileShader would, without compiling
+bool AuthenticateCombination(uint64_t hash,
+                             const uint64_t* hashes,
+                             std::size_t count) {
+  KeywordCombo compiled = shaderHashes;
+  shaderHashes.hash = hash;
+  shaderHashes.hashes.assign(hashes, hashes + count);
+  bool authentic = AuthenticateKeywords();
+  shaderHashes = compiled;
+  return authentic;
+}
+
 const char* GetKeywordsFromID(uint64_t id) {
   queryIDResult.clear();
   for (auto& s : threadKeywordCombos) {
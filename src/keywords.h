@@ -1,8 +1,22 @@
 #pragma once
 
+#include <cstdint>
 #include <iomanip>
 #include <string>
 
+// The most keywords the registry holds, one bit each in a KeywordMask
+constexpr int MaxKeywords = 256;
+
+// Set of keywords by registration order, so enabling and combining them are
+// single bit operations instead of searches
+struct KeywordMask {
+  uint64_t bits[MaxKeywords / 64];
+
+  void Set(int bit) { bits[bit >> 6] |= uint64_t(1) << (bit & 63); }
+  void Clear(int bit) { bits[bit >> 6] &= ~(uint64_t(1) << (bit & 63)); }
+  bool Test(int bit) const { return bits[bit >> 6] >> (bit & 63) & 1; }
+};
+
 uint32_t Hash32(const char *name);
 uint32_t Hash32(std::string &name);
 uint64_t Hash(const char *name);
@@ -12,6 +26,12 @@ void AddKeyword(uint64_t hash, const char *keyword);
 void AddKeyword(uint64_t hash, const std::string &keyword);
 uint64_t FindKeyword(const char *keyword);
 bool AuthenticateKeywords();
+void AddEnabledKeywords();
+
+struct RawKeyword {
+  const char *keyword;
+  uint64_t hash;
+};
 
 extern "C" {
 uint64_t ReserveKeyword(const char *keyword);
@@ -21,5 +41,20 @@ bool DisableKeyword(const char *keyword);
 bool DisableGlobalKeyword(const char *keyword);
 uint64_t AddKeyword(const char *keyword);
 const char *GetKeywordsFromID(uint64_t id);
+bool AuthenticateCombination(uint64_t hash,
+                             const uint64_t *hashes,
+                             std::size_t count);
 const char *PrintKeywords();
+const RawKeyword *ListKeywords(std::size_t *count);
+int32_t KeywordBit(const char *keyword);
+int32_t KeywordBitFromHash(uint64_t hash);
+uint64_t KeywordHashFromBit(int32_t bit);
+void GetEnabledKeywords(KeywordMask *mask);
+void SetEnabledKeywords(const KeywordMask *mask);
+void GetGlobalEnabledKeywords(KeywordMask *mask);
+void SetGlobalEnabledKeywords(const KeywordMask *mask);
+void EnableKeywords(const KeywordMask *mask, KeywordMask *previous);
+void DisableKeywords(const KeywordMask *mask);
+void EnableGlobalKeywords(const KeywordMask *mask, KeywordMask *previous);
+void DisableGlobalKeywords(const KeywordMask *mask);
 }
\ No newline at end of file
@@ -0,0 +1,755 @@
+use crate::variant::VariantKey;
+use std::collections::BTreeSet;
+use std::fmt;
+
+/// Axis entry that enables no keyword
+pub const NONE: &str = "_";
+
+/// What the entries of an axis define
+#[derive(Clone, Debug, PartialEq)]
+pub enum AxisKind {
+    /// Each entry is its own keyword, `#define LIT`
+    Flags,
+    /// Entries are `NAME=VALUE` keywords for integer values, the axis name
+    /// is the macro, `#define QUALITY 2`
+    Values,
+    /// Entries are `NAME=CONSTANT` keywords, the constants being numbered in
+    /// order by `KeywordSpace::constants`
+    Enum(Vec<String>),
+}
+
+/// One independent choice of keyword, such as `lighting: [_, LIT]` or
+/// `QUALITY = {0, 1, 2}`. `None` entries leave every keyword of the axis off.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Axis {
+    name: String,
+    kind: AxisKind,
+    keywords: Vec<Option<String>>,
+}
+
+impl Axis {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn kind(&self) -> &AxisKind {
+        &self.kind
+    }
+
+    pub fn keywords(&self) -> &[Option<String>] {
+        &self.keywords
+    }
+
+    pub fn len(&self) -> usize {
+        self.keywords.len()
+    }
+
+    pub fn index_of(&self, keyword: &str) -> Option<usize> {
+        self.keywords
+            .iter()
+            .position(|k| k.as_deref() == Some(keyword))
+    }
+
+    pub fn none_index(&self) -> Option<usize> {
+        self.keywords.iter().position(|k| k.is_none())
+    }
+}
+
+/// Rule a variant's keyword set must satisfy to be compiled
+#[derive(Clone, Debug, PartialEq)]
+pub enum Constraint {
+    /// The first keyword is only enabled with the second
+    Requires(String, String),
+    /// The two keywords are never enabled together
+    Excludes(String, String),
+    AtMostOne(Vec<String>),
+}
+
+impl Constraint {
+    pub fn allows<S: AsRef<str>>(&self, keywords: &[S]) -> bool {
+        let enabled = |name: &String| keywords.iter().any(|k| k.as_ref() == name);
+        match self {
+            Constraint::Requires(a, b) => !enabled(a) || enabled(b),
+            Constraint::Excludes(a, b) => !(enabled(a) && enabled(b)),
+            Constraint::AtMostOne(names) => names.iter().filter(|n| enabled(n)).count() <= 1,
+        }
+    }
+
+    pub fn keywords(&self) -> Vec<&str> {
+        match self {
+            Constraint::Requires(a, b) | Constraint::Excludes(a, b) => vec![a, b],
+            Constraint::AtMostOne(names) => names.iter().map(|n| n.as_str()).collect(),
+        }
+    }
+
+    /// `A requires B`, `A excludes B` or `at_most_one(A, B, ...)`
+    pub fn parse(text: &str) -> Result<Self, KeywordError> {
+        let text = text.trim().trim_end_matches(';').trim();
+        let bad = || KeywordError::BadConstraint(text.to_string());
+
+        if let Some(list) = text.strip_prefix("at_most_one") {
+            let list = list
+                .trim()
+                .strip_prefix('(')
+                .and_then(|l| l.strip_suffix(')'))
+                .ok_or_else(bad)?;
+            let names: Vec<String> = list.split(',').map(|n| n.trim().to_string()).collect();
+            if names.len() < 2 || names.iter().any(|n| n.is_empty()) {
+                return Err(bad());
+            }
+            return Ok(Constraint::AtMostOne(names));
+        }
+
+        let words: Vec<&str> = text.split_whitespace().collect();
+        match words.as_slice() {
+            [a, "requires", b] => Ok(Constraint::Requires(a.to_string(), b.to_string())),
+            [a, "excludes", b] => Ok(Constraint::Excludes(a.to_string(), b.to_string())),
+            _ => Err(bad()),
+        }
+    }
+}
+
+impl fmt::Display for Constraint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Constraint::Requires(a, b) => write!(f, "{} requires {}", a, b),
+            Constraint::Excludes(a, b) => write!(f, "{} excludes {}", a, b),
+            Constraint::AtMostOne(names) => write!(f, "at_most_one({})", names.join(", ")),
+        }
+    }
+}
+
+/// Variants that pass every constraint, and how many were skipped
+#[derive(Clone, Debug, PartialEq)]
+pub struct Enumeration {
+    pub permutations: Vec<Vec<usize>>,
+    pub pruned: usize,
+}
+
+/// Named keyword axes, a variant picks one entry from each. Variants are
+/// addressed by their axis indices, with the last axis varying fastest.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct KeywordSpace {
+    axes: Vec<Axis>,
+    constraints: Vec<Constraint>,
+}
+
+impl KeywordSpace {
+    pub fn new() -> Self {
+        KeywordSpace::default()
+    }
+
+    /// `_` marks the none entry. Keywords must be preprocessor identifiers
+    /// and unique across the whole space.
+    pub fn add_axis(&mut self, name: &str, keywords: &[&str]) -> Result<&mut Self, KeywordError> {
+        validate_identifier(name)?;
+        if self.axis(name).is_some() {
+            return Err(KeywordError::DuplicateAxis(name.to_string()));
+        }
+        if keywords.is_empty() {
+            return Err(KeywordError::EmptyAxis(name.to_string()));
+        }
+
+        let mut axis = Axis {
+            name: name.to_string(),
+            kind: AxisKind::Flags,
+            keywords: Vec::with_capacity(keywords.len()),
+        };
+        for &keyword in keywords {
+            let entry = if keyword == NONE {
+                None
+            } else {
+                validate_identifier(keyword)?;
+                Some(keyword.to_string())
+            };
+            let duplicate =
+                axis.keywords.contains(&entry) || entry.as_deref().is_some_and(|k| self.defines(k));
+            if duplicate {
+                return Err(KeywordError::DuplicateKeyword(keyword.to_string()));
+            }
+            axis.keywords.push(entry);
+        }
+        self.axes.push(axis);
+        Ok(self)
+    }
+
+    /// Axis defining `name` as one of the values, `#define QUALITY 2`
+    pub fn add_value_axis(
+        &mut self,
+        name: &str,
+        values: &[i64],
+    ) -> Result<&mut Self, KeywordError> {
+        let mut seen = BTreeSet::new();
+        for value in values {
+            if !seen.insert(value) {
+                return Err(KeywordError::DuplicateKeyword(format!(
+                    "{}={}",
+                    name, value
+                )));
+            }
+        }
+        let entries: Vec<String> = values.iter().map(|v| v.to_string()).collect();
+        self.add_valued(name, AxisKind::Values, &entries)
+    }
+
+    /// Axis defining `name` as one of the constants, which are numbered from
+    /// 0 in order, `#define SHADOW_FILTER PCSS`
+    pub fn add_enum_axis(
+        &mut self,
+        name: &str,
+        constants: &[&str],
+    ) -> Result<&mut Self, KeywordError> {
+        let mut entries: Vec<String> = Vec::with_capacity(constants.len());
+        for &constant in constants {
+            validate_identifier(constant)?;
+            if self.defines(constant) || constant == name || entries.iter().any(|e| e == constant) {
+                return Err(KeywordError::DuplicateKeyword(constant.to_string()));
+            }
+            entries.push(constant.to_string());
+        }
+        self.add_valued(name, AxisKind::Enum(entries.clone()), &entries)
+    }
+
+    fn add_valued(
+        &mut self,
+        name: &str,
+        kind: AxisKind,
+        values: &[String],
+    ) -> Result<&mut Self, KeywordError> {
+        validate_identifier(name)?;
+        if self.axis(name).is_some() {
+            return Err(KeywordError::DuplicateAxis(name.to_string()));
+        }
+        if self.defines(name) {
+            return Err(KeywordError::DuplicateKeyword(name.to_string()));
+        }
+        if values.is_empty() {
+            return Err(KeywordError::EmptyAxis(name.to_string()));
+        }
+        self.axes.push(Axis {
+            name: name.to_string(),
+            kind,
+            keywords: values
+                .iter()
+                .map(|v| Some(format!("{}={}", name, v)))
+                .collect(),
+        });
+        Ok(self)
+    }
+
+    // Whether a flag keyword, valued axis or enum constant already defines
+    // the macro
+    fn defines(&self, name: &str) -> bool {
+        self.axes.iter().any(|axis| match &axis.kind {
+            AxisKind::Flags => axis.index_of(name).is_some(),
+            AxisKind::Values => axis.name == name,
+            AxisKind::Enum(constants) => axis.name == name || constants.iter().any(|c| c == name),
+        })
+    }
+
+    /// `#define` lines numbering the enum constants, which have to come
+    /// before the keyword defines that use them
+    pub fn constants(&self) -> String {
+        let mut defines = String::new();
+        for axis in &self.axes {
+            if let AxisKind::Enum(constants) = &axis.kind {
+                for (value, constant) in constants.iter().enumerate() {
+                    defines.push_str(&format!("#define {} {}\n", constant, value));
+                }
+            }
+        }
+        defines
+    }
+
+    pub fn axes(&self) -> &[Axis] {
+        &self.axes
+    }
+
+    pub fn axis(&self, name: &str) -> Option<&Axis> {
+        self.axes.iter().find(|a| a.name == name)
+    }
+
+    /// Number of variants, 1 for an empty space
+    pub fn len(&self) -> usize {
+        self.axes.iter().map(|a| a.len()).product()
+    }
+
+    /// (axis, entry) of a keyword
+    pub fn find_keyword(&self, keyword: &str) -> Option<(usize, usize)> {
+        self.axes
+            .iter()
+            .enumerate()
+            .find_map(|(a, axis)| axis.index_of(keyword).map(|k| (a, k)))
+    }
+
+    /// Enabled keywords of the variant at `indices`, in axis order
+    pub fn keywords(&self, indices: &[usize]) -> Result<Vec<String>, KeywordError> {
+        if indices.len() != self.axes.len() {
+            return Err(KeywordError::AxisCount {
+                expected: self.axes.len(),
+                found: indices.len(),
+            });
+        }
+        let mut keywords = Vec::new();
+        for (axis, &index) in self.axes.iter().zip(indices) {
+            match axis.keywords.get(index) {
+                Some(Some(keyword)) => keywords.push(keyword.clone()),
+                Some(None) => {}
+                None => {
+                    return Err(KeywordError::BadIndex {
+                        axis: axis.name.clone(),
+                        index,
+                    })
+                }
+            }
+        }
+        Ok(keywords)
+    }
+
+    /// Axis indices of a keyword set. Axes with no keyword in the set take
+    /// their none entry.
+    pub fn indices<S: AsRef<str>>(&self, keywords: &[S]) -> Result<Vec<usize>, KeywordError> {
+        let mut indices: Vec<Option<usize>> = vec![None; self.axes.len()];
+        for keyword in keywords {
+            let keyword = keyword.as_ref();
+            let (axis, index) = self
+                .find_keyword(keyword)
+                .ok_or_else(|| KeywordError::UnknownKeyword(keyword.to_string()))?;
+            if let Some(previous) = indices[axis] {
+                if previous != index {
+                    let other = self.axes[axis].keywords[previous].clone().unwrap();
+                    return Err(KeywordError::Conflict(other, keyword.to_string()));
+                }
+            }
+            indices[axis] = Some(index);
+        }
+
+        self.axes
+            .iter()
+            .zip(indices)
+            .map(|(axis, index)| {
+                index
+                    .or_else(|| axis.none_index())
+                    .ok_or_else(|| KeywordError::MissingAxis(axis.name.clone()))
+            })
+            .collect()
+    }
+
+    /// Mixed-radix index of a variant, its position in `permutations`
+    pub fn index(&self, indices: &[usize]) -> Result<usize, KeywordError> {
+        self.keywords(indices)?;
+        Ok(self
+            .axes
+            .iter()
+            .zip(indices)
+            .fold(0, |index, (axis, &i)| index * axis.len() + i))
+    }
+
+    /// Axis indices of the variant at a mixed-radix index
+    pub fn indices_at(&self, mut index: usize) -> Result<Vec<usize>, KeywordError> {
+        if index >= self.len() {
+            return Err(KeywordError::IndexOutOfRange(index));
+        }
+        let mut indices = vec![0; self.axes.len()];
+        for (axis, i) in self.axes.iter().zip(indices.iter_mut()).rev() {
+            *i = index % axis.len();
+            index /= axis.len();
+        }
+        Ok(indices)
+    }
+
+    /// Stable key of the variant at `indices`
+    pub fn key(&self, indices: &[usize]) -> Result<VariantKey, KeywordError> {
+        Ok(VariantKey::new(&self.keywords(indices)?))
+    }
+
+    /// Axis indices of the variant a key was made from. Keys that include
+    /// keywords from outside the space don't resolve here, `VariantKeys`
+    /// records those.
+    pub fn resolve(&self, key: VariantKey) -> Option<Vec<usize>> {
+        self.permutations()
+            .into_iter()
+            .find(|indices| self.key(indices) == Ok(key))
+    }
+
+    /// Constraints can only name keywords already on an axis
+    pub fn add_constraint(&mut self, constraint: Constraint) -> Result<&mut Self, KeywordError> {
+        for keyword in constraint.keywords() {
+            if self.find_keyword(keyword).is_none() {
+                return Err(KeywordError::UnknownKeyword(keyword.to_string()));
+            }
+        }
+        self.constraints.push(constraint);
+        Ok(self)
+    }
+
+    pub fn parse_constraint(&mut self, text: &str) -> Result<&mut Self, KeywordError> {
+        self.add_constraint(Constraint::parse(text)?)
+    }
+
+    pub fn constraints(&self) -> &[Constraint] {
+        &self.constraints
+    }
+
+    /// Whether the variant at `indices` passes every constraint
+    pub fn allows(&self, indices: &[usize]) -> bool {
+        match self.keywords(indices) {
+            Ok(keywords) => self.constraints.iter().all(|c| c.allows(&keywords)),
+            Err(_) => false,
+        }
+    }
+
+    /// The permutations that pass every constraint, in the same order
+    pub fn enumerate(&self) -> Enumeration {
+        let all = self.permutations();
+        let total = all.len();
+        let permutations: Vec<Vec<usize>> = all.into_iter().filter(|i| self.allows(i)).collect();
+        Enumeration {
+            pruned: total - permutations.len(),
+            permutations,
+        }
+    }
+
+    /// Every variant's axis indices, last axis varying fastest, ignoring the
+    /// constraints
+    pub fn permutations(&self) -> Vec<Vec<usize>> {
+        let mut permutations = Vec::with_capacity(self.len());
+        let mut indices = vec![0; self.axes.len()];
+        loop {
+            permutations.push(indices.clone());
+
+            let mut axis = self.axes.len();
+            loop {
+                if axis == 0 {
+                    return permutations;
+                }
+                axis -= 1;
+                indices[axis] += 1;
+                if indices[axis] != self.axes[axis].len() {
+                    break;
+                }
+                indices[axis] = 0;
+            }
+        }
+    }
+
+    /// Adds an axis from the text after `variants` in an ESL file, one of
+    /// `name: [_, LIT]`, `_ LIT`, `QUALITY = {0, 1, 2}` or
+    /// `SHADOW_FILTER = {PCF3, PCF5, PCSS}`. Unnamed axes are called `axisN`.
+    pub fn parse_axis(&mut self, statement: &str) -> Result<&mut Self, KeywordError> {
+        let entries = |list: &str| -> Vec<String> {
+            list.split(|c: char| c.is_whitespace() || ",[]{};".contains(c))
+                .filter(|k| !k.is_empty())
+                .map(|k| k.to_string())
+                .collect()
+        };
+
+        if let Some((name, list)) = statement.split_once('=') {
+            let entries = entries(list);
+            let values: Result<Vec<i64>, _> = entries.iter().map(|e| e.parse()).collect();
+            return match values {
+                Ok(values) if !values.is_empty() => self.add_value_axis(name.trim(), &values),
+                _ => {
+                    let constants: Vec<&str> = entries.iter().map(|e| e.as_str()).collect();
+                    self.add_enum_axis(name.trim(), &constants)
+                }
+            };
+        }
+
+        let (name, list) = match statement.split_once(':') {
+            Some((name, list)) => (name.trim().to_string(), list),
+            None => (format!("axis{}", self.axes.len()), statement),
+        };
+        let keywords = entries(list);
+        let keywords: Vec<&str> = keywords.iter().map(|k| k.as_str()).collect();
+        self.add_axis(&name, &keywords)
+    }
+}
+
+// Legal preprocessor identifier that isn't reserved by GLSL
+fn validate_identifier(name: &str) -> Result<(), KeywordError> {
+    let mut chars = name.chars();
+    let valid = match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {
+            chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        }
+        _ => false,
+    };
+    if !valid || name == NONE || name.starts_with("GL_") || name.contains("__") {
+        return Err(KeywordError::InvalidIdentifier(name.to_string()));
+    }
+    Ok(())
+}
+
+#[derive(Debug, PartialEq)]
+pub enum KeywordError {
+    InvalidIdentifier(String),
+    DuplicateAxis(String),
+    DuplicateKeyword(String),
+    EmptyAxis(String),
+    UnknownKeyword(String),
+    /// Two keywords from the same axis
+    Conflict(String, String),
+    /// Axis with no keyword given and no none entry
+    MissingAxis(String),
+    BadIndex {
+        axis: String,
+        index: usize,
+    },
+    AxisCount {
+        expected: usize,
+        found: usize,
+    },
+    /// Mixed-radix index past the last variant
+    IndexOutOfRange(usize),
+    BadConstraint(String),
+}
+
+impl fmt::Display for KeywordError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            KeywordError::InvalidIdentifier(name) => {
+                write!(f, "'{}' is not a valid preprocessor identifier", name)
+            }
+            KeywordError::DuplicateAxis(name) => write!(f, "axis '{}' is already defined", name),
+            KeywordError::DuplicateKeyword(keyword) => {
+                write!(f, "keyword '{}' is already in the keyword space", keyword)
+            }
+            KeywordError::EmptyAxis(name) => write!(f, "axis '{}' has no keywords", name),
+            KeywordError::UnknownKeyword(keyword) => {
+                write!(f, "keyword '{}' is not in the keyword space", keyword)
+            }
+            KeywordError::Conflict(a, b) => {
+                write!(f, "keywords '{}' and '{}' are on the same axis", a, b)
+            }
+            KeywordError::MissingAxis(name) => {
+                write!(
+                    f,
+                    "axis '{}' needs a keyword, it has no '{}' entry",
+                    name, NONE
+                )
+            }
+            KeywordError::BadIndex { axis, index } => {
+                write!(f, "index {} is out of range for axis '{}'", index, axis)
+            }
+            KeywordError::AxisCount { expected, found } => {
+                write!(f, "expected {} axis indices, found {}", expected, found)
+            }
+            KeywordError::IndexOutOfRange(index) => {
+                write!(f, "variant index {} is out of range", index)
+            }
+            KeywordError::BadConstraint(text) => {
+                write!(
+                    f,
+                    "'{}' is not 'A requires B', 'A excludes B' or 'at_most_one(A, B, ...)'",
+                    text
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for KeywordError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn value_axis_rejects_repeated_values() {
+        let mut space = KeywordSpace::new();
+        assert_eq!(
+            space.add_value_axis("QUALITY", &[0, 1, 0]).err(),
+            Some(KeywordError::DuplicateKeyword("QUALITY=0".to_string()))
+        );
+        assert!(space.axes().is_empty());
+    }
+
+    #[test]
+    fn valued_axes_define_name_value_keywords() {
+        let mut space = KeywordSpace::new();
+        space
+            .parse_axis("QUALITY = {0, 1, 2}")
+            .and_then(|s| s.parse_axis("SHADOW_FILTER = {PCF3, PCF5, PCSS}"))
+            .unwrap();
+        assert_eq!(space.axis("QUALITY").unwrap().kind(), &AxisKind::Values);
+        assert_eq!(space.len(), 9);
+        assert_eq!(
+            space.keywords(&[2, 1]).unwrap(),
+            ["QUALITY=2", "SHADOW_FILTER=PCF5"]
+        );
+        assert_eq!(
+            space.constants(),
+            "#define PCF3 0\n#define PCF5 1\n#define PCSS 2\n"
+        );
+        // Valued axes have no none entry
+        assert_eq!(
+            space.indices(&["QUALITY=1"]),
+            Err(KeywordError::MissingAxis("SHADOW_FILTER".to_string()))
+        );
+    }
+
+    #[test]
+    fn enum_constants_cannot_shadow_keywords() {
+        let mut space = KeywordSpace::new();
+        space.add_axis("lighting", &["_", "LIT"]).unwrap();
+        assert!(space.add_enum_axis("MODE", &["LIT", "UNLIT"]).is_err());
+        assert!(space.add_enum_axis("MODE", &["A", "A"]).is_err());
+        assert!(space.add_value_axis("LIT", &[0, 1]).is_err());
+    }
+
+    fn lighting_and_fog() -> KeywordSpace {
+        let mut space = KeywordSpace::new();
+        space
+            .parse_axis("lighting: [_, LIT, UNLIT]")
+            .and_then(|s| s.parse_axis("_ FOG"))
+            .unwrap();
+        space
+    }
+
+    #[test]
+    fn parses_named_and_unnamed_axes() {
+        let space = lighting_and_fog();
+        assert_eq!(space.axes()[0].name(), "lighting");
+        assert_eq!(space.axes()[1].name(), "axis1");
+        assert_eq!(
+            space.axes()[0].keywords(),
+            [None, Some("LIT".to_string()), Some("UNLIT".to_string())]
+        );
+        assert_eq!(space.len(), 6);
+        assert_eq!(space.find_keyword("FOG"), Some((1, 1)));
+    }
+
+    #[test]
+    fn indices_round_trip_through_the_mixed_radix_index() {
+        let space = lighting_and_fog();
+        let permutations = space.permutations();
+        assert_eq!(permutations.len(), space.len());
+        assert_eq!(permutations[1], [0, 1]);
+        for (index, indices) in permutations.iter().enumerate() {
+            assert_eq!(space.index(indices), Ok(index));
+            assert_eq!(space.indices_at(index).as_ref(), Ok(indices));
+            let keywords = space.keywords(indices).unwrap();
+            assert_eq!(space.indices(&keywords).as_ref(), Ok(indices));
+        }
+        assert_eq!(space.indices_at(6), Err(KeywordError::IndexOutOfRange(6)));
+    }
+
+    #[test]
+    fn rejects_bad_keyword_sets() {
+        let space = lighting_and_fog();
+        assert_eq!(
+            space.indices(&["LIT", "UNLIT"]),
+            Err(KeywordError::Conflict(
+                "LIT".to_string(),
+                "UNLIT".to_string()
+            ))
+        );
+        assert_eq!(
+            space.indices(&["SHADOWS"]),
+            Err(KeywordError::UnknownKeyword("SHADOWS".to_string()))
+        );
+        assert_eq!(
+            space.keywords(&[3, 0]),
+            Err(KeywordError::BadIndex {
+                axis: "lighting".to_string(),
+                index: 3
+            })
+        );
+        assert_eq!(
+            space.keywords(&[0]),
+            Err(KeywordError::AxisCount {
+                expected: 2,
+                found: 1
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_bad_axes() {
+        let mut space = lighting_and_fog();
+        for name in &["1ST", "GL_LIT", "A__B", "NO-FOG"] {
+            assert_eq!(
+                space.add_axis("other", &["_", name]).err(),
+                Some(KeywordError::InvalidIdentifier(name.to_string()))
+            );
+        }
+        assert_eq!(
+            space.add_axis("lighting", &["_", "BAKED"]).err(),
+            Some(KeywordError::DuplicateAxis("lighting".to_string()))
+        );
+        assert_eq!(
+            space.add_axis("other", &["_", "FOG"]).err(),
+            Some(KeywordError::DuplicateKeyword("FOG".to_string()))
+        );
+        assert_eq!(
+            space.add_axis("other", &[]).err(),
+            Some(KeywordError::EmptyAxis("other".to_string()))
+        );
+        assert_eq!(space.axes().len(), 2);
+    }
+
+    #[test]
+    fn parses_constraints() {
+        let parse = |text| Constraint::parse(text).unwrap();
+        assert_eq!(
+            parse("FOG requires LIT;"),
+            Constraint::Requires("FOG".to_string(), "LIT".to_string())
+        );
+        assert_eq!(
+            parse(" LIT excludes UNLIT "),
+            Constraint::Excludes("LIT".to_string(), "UNLIT".to_string())
+        );
+        let at_most_one = parse("at_most_one(LIT, UNLIT, FOG)");
+        assert_eq!(at_most_one.to_string(), "at_most_one(LIT, UNLIT, FOG)");
+        assert_eq!(Constraint::parse(&at_most_one.to_string()), Ok(at_most_one));
+
+        for text in &[
+            "FOG needs LIT",
+            "at_most_one(LIT)",
+            "at_most_one(LIT,)",
+            "LIT",
+        ] {
+            assert_eq!(
+                Constraint::parse(text),
+                Err(KeywordError::BadConstraint(text.to_string()))
+            );
+        }
+    }
+
+    #[test]
+    fn constraints_allow_keyword_sets() {
+        let requires = Constraint::Requires("FOG".to_string(), "LIT".to_string());
+        assert!(requires.allows::<&str>(&[]));
+        assert!(requires.allows(&["LIT"]));
+        assert!(requires.allows(&["FOG", "LIT"]));
+        assert!(!requires.allows(&["FOG"]));
+
+        let excludes = Constraint::Excludes("LIT".to_string(), "FOG".to_string());
+        assert!(excludes.allows(&["FOG"]));
+        assert!(!excludes.allows(&["FOG", "LIT"]));
+
+        let at_most_one = Constraint::AtMostOne(vec!["A".to_string(), "B".to_string()]);
+        assert!(at_most_one.allows(&["A", "C"]));
+        assert!(!at_most_one.allows(&["A", "B"]));
+    }
+
+    #[test]
+    fn enumerate_prunes_disallowed_permutations() {
+        let mut space = lighting_and_fog();
+        space.parse_constraint("FOG requires LIT").unwrap();
+        let enumeration = space.enumerate();
+        assert_eq!(enumeration.pruned, 2);
+        assert_eq!(
+            enumeration.permutations,
+            [vec![0, 0], vec![1, 0], vec![1, 1], vec![2, 0]]
+        );
+        assert!(!space.allows(&[2, 1]));
+
+        assert_eq!(
+            space.parse_constraint("FOG requires SHADOWS").err(),
+            Some(KeywordError::UnknownKeyword("SHADOWS".to_string()))
+        );
+        assert_eq!(space.constraints().len(), 1);
+    }
+}
@@ -0,0 +1,462 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Mirrors glslang's `TBuiltInResource` field for field, so it can be handed
+/// straight to `Initialise`. Profiles loaded from TOML/JSON only need to list
+/// the fields they change, plus an optional `base` preset to start from.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ResourceLimits {
+    pub max_lights: i32,
+    pub max_clip_planes: i32,
+    pub max_texture_units: i32,
+    pub max_texture_coords: i32,
+    pub max_vertex_attribs: i32,
+    pub max_vertex_uniform_components: i32,
+    pub max_varying_floats: i32,
+    pub max_vertex_texture_image_units: i32,
+    pub max_combined_texture_image_units: i32,
+    pub max_texture_image_units: i32,
+    pub max_fragment_uniform_components: i32,
+    pub max_draw_buffers: i32,
+    pub max_vertex_uniform_vectors: i32,
+    pub max_varying_vectors: i32,
+    pub max_fragment_uniform_vectors: i32,
+    pub max_vertex_output_vectors: i32,
+    pub max_fragment_input_vectors: i32,
+    pub min_program_texel_offset: i32,
+    pub max_program_texel_offset: i32,
+    pub max_clip_distances: i32,
+    pub max_compute_work_group_count_x: i32,
+    pub max_compute_work_group_count_y: i32,
+    pub max_compute_work_group_count_z: i32,
+    pub max_compute_work_group_size_x: i32,
+    pub max_compute_work_group_size_y: i32,
+    pub max_compute_work_group_size_z: i32,
+    pub max_compute_uniform_components: i32,
+    pub max_compute_texture_image_units: i32,
+    pub max_compute_image_uniforms: i32,
+    pub max_compute_atomic_counters: i32,
+    pub max_compute_atomic_counter_buffers: i32,
+    pub max_varying_components: i32,
+    pub max_vertex_output_components: i32,
+    pub max_geometry_input_components: i32,
+    pub max_geometry_output_components: i32,
+    pub max_fragment_input_components: i32,
+    pub max_image_units: i32,
+    pub max_combined_image_units_and_fragment_outputs: i32,
+    pub max_combined_shader_output_resources: i32,
+    pub max_image_samples: i32,
+    pub max_vertex_image_uniforms: i32,
+    pub max_tess_control_image_uniforms: i32,
+    pub max_tess_evaluation_image_uniforms: i32,
+    pub max_geometry_image_uniforms: i32,
+    pub max_fragment_image_uniforms: i32,
+    pub max_combined_image_uniforms: i32,
+    pub max_geometry_texture_image_units: i32,
+    pub max_geometry_output_vertices: i32,
+    pub max_geometry_total_output_components: i32,
+    pub max_geometry_uniform_components: i32,
+    pub max_geometry_varying_components: i32,
+    pub max_tess_control_input_components: i32,
+    pub max_tess_control_output_components: i32,
+    pub max_tess_control_texture_image_units: i32,
+    pub max_tess_control_uniform_components: i32,
+    pub max_tess_control_total_output_components: i32,
+    pub max_tess_evaluation_input_components: i32,
+    pub max_tess_evaluation_output_components: i32,
+    pub max_tess_evaluation_texture_image_units: i32,
+    pub max_tess_evaluation_uniform_components: i32,
+    pub max_tess_patch_components: i32,
+    pub max_patch_vertices: i32,
+    pub max_tess_gen_level: i32,
+    pub max_viewports: i32,
+    pub max_vertex_atomic_counters: i32,
+    pub max_tess_control_atomic_counters: i32,
+    pub max_tess_evaluation_atomic_counters: i32,
+    pub max_geometry_atomic_counters: i32,
+    pub max_fragment_atomic_counters: i32,
+    pub max_combined_atomic_counters: i32,
+    pub max_atomic_counter_bindings: i32,
+    pub max_vertex_atomic_counter_buffers: i32,
+    pub max_tess_control_atomic_counter_buffers: i32,
+    pub max_tess_evaluation_atomic_counter_buffers: i32,
+    pub max_geometry_atomic_counter_buffers: i32,
+    pub max_fragment_atomic_counter_buffers: i32,
+    pub max_combined_atomic_counter_buffers: i32,
+    pub max_atomic_counter_buffer_size: i32,
+    pub max_transform_feedback_buffers: i32,
+    pub max_transform_feedback_interleaved_components: i32,
+    pub max_cull_distances: i32,
+    pub max_combined_clip_and_cull_distances: i32,
+    pub max_samples: i32,
+    pub max_mesh_output_vertices_nv: i32,
+    pub max_mesh_output_primitives_nv: i32,
+    pub max_mesh_work_group_size_xnv: i32,
+    pub max_mesh_work_group_size_ynv: i32,
+    pub max_mesh_work_group_size_znv: i32,
+    pub max_task_work_group_size_xnv: i32,
+    pub max_task_work_group_size_ynv: i32,
+    pub max_task_work_group_size_znv: i32,
+    pub max_mesh_view_count_nv: i32,
+    pub limits: Limits,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Limits {
+    pub non_inductive_for_loops: bool,
+    pub while_loops: bool,
+    pub do_while_loops: bool,
+    pub general_uniform_indexing: bool,
+    pub general_attribute_matrix_vector_indexing: bool,
+    pub general_varying_indexing: bool,
+    pub general_sampler_indexing: bool,
+    pub general_variable_indexing: bool,
+    pub general_constant_matrix_vector_indexing: bool,
+}
+
+impl Default for ResourceLimits {
+    /// The `DefaultTBuiltInResource` table from spirv.cpp
+    fn default() -> Self {
+        ResourceLimits {
+            max_lights: 32,
+            max_clip_planes: 6,
+            max_texture_units: 32,
+            max_texture_coords: 32,
+            max_vertex_attribs: 64,
+            max_vertex_uniform_components: 4096,
+            max_varying_floats: 64,
+            max_vertex_texture_image_units: 32,
+            max_combined_texture_image_units: 80,
+            max_texture_image_units: 32,
+            max_fragment_uniform_components: 4096,
+            max_draw_buffers: 32,
+            max_vertex_uniform_vectors: 128,
+            max_varying_vectors: 8,
+            max_fragment_uniform_vectors: 16,
+            max_vertex_output_vectors: 16,
+            max_fragment_input_vectors: 15,
+            min_program_texel_offset: -8,
+            max_program_texel_offset: 7,
+            max_clip_distances: 8,
+            max_compute_work_group_count_x: 65535,
+            max_compute_work_group_count_y: 65535,
+            max_compute_work_group_count_z: 65535,
+            max_compute_work_group_size_x: 1024,
+            max_compute_work_group_size_y: 1024,
+            max_compute_work_group_size_z: 64,
+            max_compute_uniform_components: 1024,
+            max_compute_texture_image_units: 16,
+            max_compute_image_uniforms: 8,
+            max_compute_atomic_counters: 8,
+            max_compute_atomic_counter_buffers: 1,
+            max_varying_components: 60,
+            max_vertex_output_components: 64,
+            max_geometry_input_components: 64,
+            max_geometry_output_components: 128,
+            max_fragment_input_components: 128,
+            max_image_units: 8,
+            max_combined_image_units_and_fragment_outputs: 8,
+            max_combined_shader_output_resources: 8,
+            max_image_samples: 0,
+            max_vertex_image_uniforms: 0,
+            max_tess_control_image_uniforms: 0,
+            max_tess_evaluation_image_uniforms: 0,
+            max_geometry_image_uniforms: 0,
+            max_fragment_image_uniforms: 8,
+            max_combined_image_uniforms: 8,
+            max_geometry_texture_image_units: 16,
+            max_geometry_output_vertices: 256,
+            max_geometry_total_output_components: 1024,
+            max_geometry_uniform_components: 1024,
+            max_geometry_varying_components: 64,
+            max_tess_control_input_components: 128,
+            max_tess_control_output_components: 128,
+            max_tess_control_texture_image_units: 16,
+            max_tess_control_uniform_components: 1024,
+            max_tess_control_total_output_components: 4096,
+            max_tess_evaluation_input_components: 128,
+            max_tess_evaluation_output_components: 128,
+            max_tess_evaluation_texture_image_units: 16,
+            max_tess_evaluation_uniform_components: 1024,
+            max_tess_patch_components: 120,
+            max_patch_vertices: 32,
+            max_tess_gen_level: 64,
+            max_viewports: 16,
+            max_vertex_atomic_counters: 0,
+            max_tess_control_atomic_counters: 0,
+            max_tess_evaluation_atomic_counters: 0,
+            max_geometry_atomic_counters: 0,
+            max_fragment_atomic_counters: 8,
+            max_combined_atomic_counters: 8,
+            max_atomic_counter_bindings: 1,
+            max_vertex_atomic_counter_buffers: 0,
+            max_tess_control_atomic_counter_buffers: 0,
+            max_tess_evaluation_atomic_counter_buffers: 0,
+            max_geometry_atomic_counter_buffers: 0,
+            max_fragment_atomic_counter_buffers: 1,
+            max_combined_atomic_counter_buffers: 1,
+            max_atomic_counter_buffer_size: 16384,
+            max_transform_feedback_buffers: 4,
+            max_transform_feedback_interleaved_components: 64,
+            max_cull_distances: 8,
+            max_combined_clip_and_cull_distances: 8,
+            max_samples: 4,
+            max_mesh_output_vertices_nv: 256,
+            max_mesh_output_primitives_nv: 512,
+            max_mesh_work_group_size_xnv: 32,
+            max_mesh_work_group_size_ynv: 1,
+            max_mesh_work_group_size_znv: 1,
+            max_task_work_group_size_xnv: 32,
+            max_task_work_group_size_ynv: 1,
+            max_task_work_group_size_znv: 1,
+            max_mesh_view_count_nv: 4,
+            limits: Limits {
+                non_inductive_for_loops: true,
+                while_loops: true,
+                do_while_loops: true,
+                general_uniform_indexing: true,
+                general_attribute_matrix_vector_indexing: true,
+                general_varying_indexing: true,
+                general_sampler_indexing: true,
+                general_variable_indexing: true,
+                general_constant_matrix_vector_indexing: true,
+            },
+        }
+    }
+}
+
+impl ResourceLimits {
+    /// Built-in presets: "default", "desktop" and "mobile-low".
+    pub fn preset(name: &str) -> Option<Self> {
+        match name {
+            "default" => Some(ResourceLimits::default()),
+            "desktop" => Some(ResourceLimits::desktop()),
+            "mobile-low" => Some(ResourceLimits::mobile_low()),
+            _ => None,
+        }
+    }
+
+    /// Current desktop GPU limits. Every field is at least the default
+    /// table's, so anything the default accepts still compiles.
+    pub fn desktop() -> Self {
+        ResourceLimits {
+            max_varying_vectors: 32,
+            max_vertex_uniform_vectors: 4096,
+            max_fragment_uniform_vectors: 4096,
+            max_vertex_output_vectors: 32,
+            max_fragment_input_vectors: 31,
+            max_varying_components: 124,
+            max_vertex_output_components: 128,
+            max_combined_texture_image_units: 192,
+            max_image_units: 64,
+            max_combined_image_units_and_fragment_outputs: 64,
+            max_combined_shader_output_resources: 64,
+            max_compute_image_uniforms: 64,
+            max_fragment_image_uniforms: 64,
+            max_combined_image_uniforms: 64,
+            max_samples: 32,
+            ..Default::default()
+        }
+    }
+
+    /// Roughly the OpenGL ES 2.0 minimums, the lowest mobile target we ship
+    pub fn mobile_low() -> Self {
+        ResourceLimits {
+            max_vertex_attribs: 8,
+            max_vertex_uniform_components: 512,
+            max_varying_floats: 32,
+            max_vertex_texture_image_units: 0,
+            max_combined_texture_image_units: 8,
+            max_texture_image_units: 8,
+            max_fragment_uniform_components: 64,
+            max_draw_buffers: 1,
+            max_vertex_uniform_vectors: 128,
+            max_varying_vectors: 8,
+            max_fragment_uniform_vectors: 16,
+            max_vertex_output_vectors: 8,
+            max_fragment_input_vectors: 8,
+            max_varying_components: 32,
+            max_vertex_output_components: 32,
+            max_fragment_input_components: 32,
+            max_samples: 1,
+            ..Default::default()
+        }
+    }
+
+    /// A preset name, or else the path of a device profile, as given to the
+    /// tool's `--limits`
+    pub fn load(profile: &str) -> Result<Self, LimitsError> {
+        match ResourceLimits::preset(profile) {
+            Some(limits) => Ok(limits),
+            None => ResourceLimits::from_file(profile),
+        }
+    }
+
+    /// Loads a device profile, picking TOML or JSON from the file extension.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, LimitsError> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path).map_err(LimitsError::Io)?;
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("toml") => ResourceLimits::from_toml(&text),
+            Some("json") => ResourceLimits::from_json(&text),
+            _ => Err(LimitsError::UnknownFormat(path.display().to_string())),
+        }
+    }
+
+    pub fn from_toml(text: &str) -> Result<Self, LimitsError> {
+        let value: toml::Value = toml::from_str(text).map_err(LimitsError::Toml)?;
+        let profile = serde_json::to_value(value).map_err(LimitsError::Json)?;
+        ResourceLimits::from_profile(profile)
+    }
+
+    pub fn from_json(text: &str) -> Result<Self, LimitsError> {
+        let profile = serde_json::from_str(text).map_err(LimitsError::Json)?;
+        ResourceLimits::from_profile(profile)
+    }
+
+    fn from_profile(mut profile: serde_json::Value) -> Result<Self, LimitsError> {
+        let overrides = match profile.as_object_mut() {
+            Some(o) => o,
+            None => return Err(LimitsError::NotATable),
+        };
+
+        let base = match overrides.remove("base") {
+            Some(serde_json::Value::String(name)) => match ResourceLimits::preset(&name) {
+                Some(limits) => limits,
+                None => return Err(LimitsError::UnknownPreset(name)),
+            },
+            Some(_) => return Err(LimitsError::NotATable),
+            None => ResourceLimits::default(),
+        };
+
+        let mut merged = serde_json::to_value(base).map_err(LimitsError::Json)?;
+        merge(&mut merged, profile);
+        serde_json::from_value(merged).map_err(LimitsError::Json)
+    }
+}
+
+fn merge(base: &mut serde_json::Value, overrides: serde_json::Value) {
+    match (base, overrides) {
+        (serde_json::Value::Object(b), serde_json::Value::Object(o)) => {
+            for (key, value) in o {
+                match b.get_mut(&key) {
+                    Some(existing) => merge(existing, value),
+                    None => {
+                        // Left in so deny_unknown_fields reports the typo
+                        b.insert(key, value);
+                    }
+                }
+            }
+        }
+        (b, o) => *b = o,
+    }
+}
+
+#[derive(Debug)]
+pub enum LimitsError {
+    Io(io::Error),
+    Toml(toml::de::Error),
+    Json(serde_json::Error),
+    UnknownFormat(String),
+    UnknownPreset(String),
+    NotATable,
+}
+
+impl fmt::Display for LimitsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LimitsError::Io(e) => write!(f, "cannot read device profile: {}", e),
+            LimitsError::Toml(e) => write!(f, "invalid TOML device profile: {}", e),
+            LimitsError::Json(e) => write!(f, "invalid device profile: {}", e),
+            LimitsError::UnknownFormat(name) => {
+                write!(f, "'{}' is neither a .toml nor a .json profile", name)
+            }
+            LimitsError::UnknownPreset(name) => write!(f, "unknown limits preset '{}'", name),
+            LimitsError::NotATable => write!(f, "device profile must be a table of limits"),
+        }
+    }
+}
+
+impl std::error::Error for LimitsError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::options::CompileOptions;
+    use crate::{ShaderCompiler, Stage};
+
+    // Nine vec4 outputs, one over mobile-low's eight
+    const NINE_OUTPUTS: &str = "#version 450
+layout(location = 0) out vec4 outputs[9];
+void main() {
+    for (int i = 0; i < 9; i++)
+        outputs[i] = vec4(i);
+    gl_Position = vec4(0.0);
+}
+";
+
+    #[test]
+    fn layout_matches_tbuiltinresource() {
+        // The static_assert in spirv.cpp checks the same size
+        assert_eq!(std::mem::size_of::<ResourceLimits>(), 92 * 4 + 12);
+    }
+
+    #[test]
+    fn load_takes_a_preset_or_a_file() {
+        assert_eq!(
+            ResourceLimits::load("mobile-low").unwrap(),
+            ResourceLimits::mobile_low()
+        );
+        let path = std::env::temp_dir().join(format!("limits-{}.toml", std::process::id()));
+        fs::write(&path, "base = \"desktop\"\nmax_samples = 8\n").unwrap();
+        let limits = ResourceLimits::load(path.to_str().unwrap());
+        fs::remove_file(&path).unwrap();
+        assert_eq!(limits.unwrap().max_samples, 8);
+        assert!(matches!(
+            ResourceLimits::load("mobile"),
+            Err(LimitsError::Io(_))
+        ));
+    }
+
+    #[test]
+    fn varying_vectors_are_checked_against_the_profile() {
+        let options = CompileOptions::default();
+        {
+            let mut compiler = ShaderCompiler::new(&ResourceLimits::mobile_low());
+            assert_eq!(
+                compiler.compile(Stage::VertexStage, NINE_OUTPUTS, &options),
+                0
+            );
+        }
+        let mut compiler = ShaderCompiler::new(&ResourceLimits::default());
+        assert_ne!(
+            compiler.compile(Stage::VertexStage, NINE_OUTPUTS, &options),
+            0
+        );
+    }
+
+    #[test]
+    fn desktop_is_never_below_default() {
+        let desktop = serde_json::to_value(ResourceLimits::desktop()).unwrap();
+        let default = serde_json::to_value(ResourceLimits::default()).unwrap();
+        for (field, value) in default.as_object().unwrap() {
+            let (value, desktop) = match (value.as_i64(), desktop[field].as_i64()) {
+                (Some(value), Some(desktop)) => (value, desktop),
+                _ => continue,
+            };
+            // The minimum texel offset is the one limit where lower is looser
+            if field == "min_program_texel_offset" {
+                assert!(desktop <= value, "{}", field);
+            } else {
+                assert!(desktop >= value, "{}", field);
+            }
+        }
+    }
+}
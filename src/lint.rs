@@ -0,0 +1,300 @@
+use crate::keywords::{AxisKind, KeywordSpace};
+use std::collections::BTreeSet;
+
+/// Declared keywords compared with the macros the shader source tests.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct KeywordUsage {
+    /// Declared but never tested, each doubles the variant count or more for
+    /// identical code
+    pub untested: Vec<String>,
+    /// Tested but never declared or defined, usually a misspelled keyword
+    pub undeclared: Vec<UndeclaredMacro>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct UndeclaredMacro {
+    pub name: String,
+    /// Declared keyword a letter or two away
+    pub suggestion: Option<String>,
+}
+
+impl KeywordUsage {
+    /// Checks the space against every source the variants compile, stages
+    /// and includes alike
+    pub fn check<'a, I: IntoIterator<Item = &'a str>>(space: &KeywordSpace, sources: I) -> Self {
+        let mut tested = BTreeSet::new();
+        let mut defined = BTreeSet::new();
+        for source in sources {
+            scan(source, &mut tested, &mut defined);
+        }
+
+        // Flag keywords are macros themselves, valued axes define their name
+        // and enum axes their constants too
+        let mut declared: Vec<String> = Vec::new();
+        for axis in space.axes() {
+            match axis.kind() {
+                AxisKind::Flags => declared.extend(axis.keywords().iter().flatten().cloned()),
+                AxisKind::Values => declared.push(axis.name().to_string()),
+                AxisKind::Enum(constants) => {
+                    declared.push(axis.name().to_string());
+                    defined.extend(constants.iter().cloned());
+                }
+            }
+        }
+
+        KeywordUsage {
+            untested: declared
+                .iter()
+                .filter(|name| !tested.contains(*name))
+                .cloned()
+                .collect(),
+            undeclared: tested
+                .iter()
+                .filter(|name| !declared.contains(name) && !defined.contains(*name))
+                .filter(|name| !builtin(name))
+                .map(|name| UndeclaredMacro {
+                    name: name.clone(),
+                    suggestion: suggest(name, &declared),
+                })
+                .collect(),
+        }
+    }
+
+    pub fn is_clean(&self) -> bool {
+        self.untested.is_empty() && self.undeclared.is_empty()
+    }
+
+    pub fn warnings(&self) -> Vec<String> {
+        let mut warnings: Vec<String> = self
+            .untested
+            .iter()
+            .map(|name| format!("keyword '{}' is declared but never tested", name))
+            .collect();
+        for undeclared in &self.undeclared {
+            warnings.push(match &undeclared.suggestion {
+                Some(suggestion) => format!(
+                    "'{}' is tested but not declared, did you mean '{}'?",
+                    undeclared.name, suggestion
+                ),
+                None => format!("'{}' is tested but not declared", undeclared.name),
+            });
+        }
+        warnings
+    }
+}
+
+/// Macros a source tests with `#ifdef`, `#ifndef`, `#if` or `#elif`
+pub fn tested_macros(source: &str) -> BTreeSet<String> {
+    let mut tested = BTreeSet::new();
+    scan(source, &mut tested, &mut BTreeSet::new());
+    tested
+}
+
+// Collects the macros `#ifdef`, `#ifndef`, `#if` and `#elif` test, and the
+// ones the source `#define`s itself
+fn scan(source: &str, tested: &mut BTreeSet<String>, defined: &mut BTreeSet<String>) {
+    for line in strip_comments(source).lines() {
+        let line = line.trim();
+        let directive = match line.strip_prefix('#') {
+            Some(directive) => directive.trim_start(),
+            None => continue,
+        };
+        let name_end = directive
+            .find(|c: char| !c.is_ascii_alphanumeric())
+            .unwrap_or(directive.len());
+        let (name, rest) = directive.split_at(name_end);
+        let mut identifiers = identifiers(rest);
+        match name {
+            "ifdef" | "ifndef" => tested.extend(identifiers.next()),
+            "if" | "elif" => tested.extend(identifiers.filter(|i| i != "defined")),
+            "define" => defined.extend(identifiers.next()),
+            _ => {}
+        }
+    }
+}
+
+// Source without `//` and `/* */` comments. As in the preprocessor a block
+// comment becomes one space, so a directive continues past a multi-line one
+fn strip_comments(source: &str) -> String {
+    let mut out = String::with_capacity(source.len());
+    let mut chars = source.chars().peekable();
+    let mut in_line = false;
+    let mut in_block = false;
+    while let Some(c) = chars.next() {
+        if in_block {
+            if c == '*' && chars.peek() == Some(&'/') {
+                chars.next();
+                in_block = false;
+                out.push(' ');
+            }
+        } else if in_line {
+            if c == '\n' {
+                in_line = false;
+                out.push(c);
+            }
+        } else if c == '/' && chars.peek() == Some(&'/') {
+            chars.next();
+            in_line = true;
+        } else if c == '/' && chars.peek() == Some(&'*') {
+            chars.next();
+            in_block = true;
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+fn identifiers(expression: &str) -> impl Iterator<Item = String> + '_ {
+    expression
+        .split(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+        .filter(|token| token.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_'))
+        .map(|token| token.to_string())
+}
+
+// Macros glslang defines itself
+fn builtin(name: &str) -> bool {
+    name.starts_with("GL_") || (name.starts_with("__") && name.ends_with("__")) || name == "VULKAN"
+}
+
+fn suggest(name: &str, declared: &[String]) -> Option<String> {
+    // Short names are a couple of edits from anything
+    let limit = (name.len() / 4).clamp(1, 2);
+    declared
+        .iter()
+        .map(|candidate| (distance(name, candidate), candidate))
+        .filter(|(d, _)| *d <= limit)
+        .min_by_key(|(d, _)| *d)
+        .map(|(_, candidate)| candidate.clone())
+}
+
+// Edit distance counting a swap of neighbouring letters as one edit, so
+// LOD_BAIS is one away from LOD_BIAS
+fn distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let mut rows = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in rows.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, cell) in rows[0].iter_mut().enumerate() {
+        *cell = j;
+    }
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = if a[i - 1] == b[j - 1] { 0 } else { 1 };
+            let mut d = (rows[i - 1][j] + 1)
+                .min(rows[i][j - 1] + 1)
+                .min(rows[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                d = d.min(rows[i - 2][j - 2] + 1);
+            }
+            rows[i][j] = d;
+        }
+    }
+    rows[a.len()][b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn space() -> KeywordSpace {
+        let mut space = KeywordSpace::new();
+        space
+            .add_axis("lighting", &["_", "LIT", "UNLIT"])
+            .and_then(|s| s.add_axis("lod", &["_", "LOD_BIAS"]))
+            .and_then(|s| s.add_value_axis("QUALITY", &[0, 1]))
+            .and_then(|s| s.add_enum_axis("SHADOW_FILTER", &["PCF3", "PCSS"]))
+            .unwrap();
+        space
+    }
+
+    const SOURCE: &str = "
+#version 450
+#define SAMPLES 4
+#ifdef LIT
+#elif defined(LOD_BAIS) && QUALITY > 1 // UNLIT
+#endif
+#if SHADOW_FILTER == PCSS || SAMPLES > 2
+#endif
+#ifndef GL_ES
+#endif
+#if defined(__VERSION__) || defined(VULKAN)
+#endif
+";
+
+    #[test]
+    fn finds_untested_and_undeclared_macros() {
+        let usage = KeywordUsage::check(&space(), vec![SOURCE]);
+        assert_eq!(usage.untested, ["UNLIT", "LOD_BIAS"]);
+        assert_eq!(
+            usage.undeclared,
+            [UndeclaredMacro {
+                name: "LOD_BAIS".to_string(),
+                suggestion: Some("LOD_BIAS".to_string()),
+            }]
+        );
+        assert!(!usage.is_clean());
+        assert_eq!(
+            usage.warnings()[2],
+            "'LOD_BAIS' is tested but not declared, did you mean 'LOD_BIAS'?"
+        );
+    }
+
+    #[test]
+    fn checks_every_source() {
+        let sources = vec![
+            SOURCE,
+            "#ifdef UNLIT\n#endif\n#if LOD_BIAS\n#endif\n#ifdef FOG\n#endif",
+        ];
+        let usage = KeywordUsage::check(&space(), sources);
+        assert!(usage.untested.is_empty());
+        let names: Vec<&str> = usage.undeclared.iter().map(|u| u.name.as_str()).collect();
+        assert_eq!(names, ["FOG", "LOD_BAIS"]);
+        assert_eq!(usage.undeclared[0].suggestion, None);
+    }
+
+    #[test]
+    fn collects_tested_macros() {
+        let tested: Vec<String> = tested_macros(SOURCE).into_iter().collect();
+        assert_eq!(
+            tested,
+            [
+                "GL_ES",
+                "LIT",
+                "LOD_BAIS",
+                "PCSS",
+                "QUALITY",
+                "SAMPLES",
+                "SHADOW_FILTER",
+                "VULKAN",
+                "__VERSION__"
+            ]
+        );
+    }
+
+    #[test]
+    fn skips_block_comments() {
+        let source = "/* #ifdef OLD_SHADOWS
+#if QUALITY > 1
+*/
+#ifdef /* not SPECULAR */ LIT
+#endif
+#if defined(FOG) /* || defined(HAZE)
+#ifdef MIST */ || defined(RAIN)
+#endif
+";
+        let tested: Vec<String> = tested_macros(source).into_iter().collect();
+        assert_eq!(tested, ["FOG", "LIT", "RAIN"]);
+        assert_eq!(strip_comments("a /* b\nc */ d // e\nf"), "a   d \nf");
+    }
+
+    #[test]
+    fn counts_swapped_letters_as_one_edit() {
+        assert_eq!(distance("LOD_BAIS", "LOD_BIAS"), 1);
+        assert_eq!(distance("LIT", "LIT"), 0);
+        assert_eq!(distance("LIT", "FOG"), 3);
+        assert_eq!(suggest("FOG", &["LIT".to_string()]), None);
+    }
+}
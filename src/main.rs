@@ -3,25 +3,64 @@ Author: Richard Underhill 2020
 */
 
 #![allow(dead_code)]
+mod builder;
+mod codegen;
+mod collection;
+mod cross;
 mod freetype;
 mod glfw;
+mod keywords;
+mod limits;
+mod lint;
+mod mask;
+mod module;
+mod optimizer;
+mod options;
+mod preamble;
+mod reflect;
+mod registry;
+mod scope;
+mod selector;
 mod shader;
+mod validation;
+mod variant;
 mod vulkan;
 
+use builder::{VariantBuilder, VariantResult};
+use collection::{CollectionError, VariantCollection};
 use core::ptr::null;
+use cross::{
+    BindingManifest, BindingRemap, BindingSlot, CrossCompiled, GlslOptions, HlslOptions,
+    MslOptions, RawBinding, RawGlesCompat,
+};
+use keywords::KeywordSpace;
+use limits::ResourceLimits;
+use mask::KeywordMask;
+use module::{SpirvError, SpirvModule};
+use optimizer::{OptLevel, OptReport};
+use options::CompileOptions;
+use preamble::{PreambleGuard, PreambleScope, PreambleStack};
+use reflect::{RawReflection, ShaderReflection};
+use registry::{KeywordRegistry, RawKeyword, RegisteredKeyword, RegistryError};
+use scope::{GlobalKeywordScope, KeywordScope, ToggleError};
+use selector::VariantSelector;
 use shader::Parser;
+use std::collections::BTreeSet;
+use std::convert::TryFrom;
 use std::ffi::{CStr, CString};
 use std::fs;
 use std::fs::read_to_string;
-use std::fs::File;
-use std::io::prelude::*;
+use std::io;
 use std::os::raw::c_char;
 use std::ptr::copy;
+use validation::{RawMessage, ValidationMessage};
+use variant::{VariantKey, VariantKeyError, VariantKeys};
 
 use core::ffi::c_void;
 use glfw::{glfwInit, glfwTerminate, glfwVulkanSupported};
 const GLFW_TRUE: i32 = 1;
 
+#[derive(Copy, Clone, Debug, PartialEq)]
 enum Stage {
     VertexStage,
     TessControlStage,
@@ -31,62 +70,123 @@ enum Stage {
     ComputeStage,
 }
 
+impl Stage {
+    // spv::ExecutionModel values
+    pub fn from_execution_model(model: u32) -> Option<Stage> {
+        match model {
+            0 => Some(Stage::VertexStage),
+            1 => Some(Stage::TessControlStage),
+            2 => Some(Stage::TessEvaluationStage),
+            3 => Some(Stage::GeometryStage),
+            4 => Some(Stage::FragmentStage),
+            5 => Some(Stage::ComputeStage),
+            _ => None,
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Stage::VertexStage => "vert",
+            Stage::TessControlStage => "tesc",
+            Stage::TessEvaluationStage => "tese",
+            Stage::GeometryStage => "geom",
+            Stage::FragmentStage => "frag",
+            Stage::ComputeStage => "comp",
+        }
+    }
+}
+
 enum GraphicsAPI {
-    GLSL,
-    HLSL,
-    Metal,
+    GLSL(GlslOptions),
+    HLSL(HlslOptions),
+    Metal(MslOptions),
+    /// SPIRV-Cross's JSON reflection instead of source
+    Reflection,
 }
 
 extern "C" {
 
-    fn Initialise();
+    fn Initialise(resources: *const ResourceLimits);
     fn GetSpirvSize() -> usize;
     fn CreateProgram() -> *const c_void;
     fn DeleteProgram(program: *const c_void);
-    fn CompileShader(stage: i32, source: *const c_char) -> usize;
+    fn CompileShader(stage: i32, source: *const c_char, options: u64) -> usize;
     fn GetShader(handle: usize) -> *const c_void;
     fn Add(program: *const c_void, handle: usize);
     fn AddKeyword(program: *const c_char) -> u64;
     fn GetKeywordsID() -> u64;
+    fn KeywordsCollided() -> bool;
     fn GetKeywordsFromID(id: u64) -> *const c_char;
+    #[cfg(test)]
+    fn AuthenticateCombination(hash: u64, hashes: *const u64, count: usize) -> bool;
     fn ReserveKeyword(keyword: *const c_char) -> u64;
     fn EnableKeyword(keyword: *const c_char) -> bool;
     fn EnableGlobalKeyword(keyword: *const c_char) -> bool;
     fn DisableKeyword(keyword: *const c_char) -> bool;
     fn DisableGlobalKeyword(keyword: *const c_char) -> bool;
     fn PrintKeywords() -> *const c_char;
+    fn ListKeywords(count: *mut usize) -> *const RawKeyword;
+    fn KeywordBit(keyword: *const c_char) -> i32;
+    fn KeywordBitFromHash(hash: u64) -> i32;
+    fn KeywordHashFromBit(bit: i32) -> u64;
+    fn GetEnabledKeywords(mask: *mut KeywordMask);
+    fn SetEnabledKeywords(mask: *const KeywordMask);
+    fn GetGlobalEnabledKeywords(mask: *mut KeywordMask);
+    fn SetGlobalEnabledKeywords(mask: *const KeywordMask);
+    fn EnableKeywords(mask: *const KeywordMask, previous: *mut KeywordMask);
+    fn DisableKeywords(mask: *const KeywordMask);
+    fn EnableGlobalKeywords(mask: *const KeywordMask, previous: *mut KeywordMask);
+    fn DisableGlobalKeywords(mask: *const KeywordMask);
     fn ClearPreamble();
+    fn AddIncludeDir(dir: *const c_char);
+    fn AddVirtualInclude(name: *const c_char, contents: *const c_char);
+    fn GetIncludedFiles(handle: usize, count: *mut usize) -> *const *const c_char;
     fn SetPreamble(source: *const c_char);
-    fn PrintSpirv();
+    fn PrintSpirv(spirv: *const u32, length: usize);
     fn Recompile(handle: usize) -> usize;
-    fn DecompileToGLSL() -> *const c_char;
-    fn DecompileToHLSL() -> *const c_char;
-    fn DecompileToMetal() -> *const c_char;
+    fn DecompileToGLSL(
+        spirv: *const u32,
+        length: usize,
+        options: *const GlslOptions,
+    ) -> *const c_char;
+    fn DecompileToHLSL(
+        spirv: *const u32,
+        length: usize,
+        options: *const HlslOptions,
+        remaps: *const BindingSlot,
+        remap_count: usize,
+    ) -> *const c_char;
+    fn DecompileToMetal(
+        spirv: *const u32,
+        length: usize,
+        options: *const MslOptions,
+        remaps: *const BindingSlot,
+        remap_count: usize,
+    ) -> *const c_char;
+    fn DecompileToReflection(spirv: *const u32, length: usize) -> *const c_char;
+    fn GetBindingManifest(count: *mut usize) -> *const RawBinding;
+    fn GetGlesCompat() -> *const RawGlesCompat;
     fn Link(program: *const c_void) -> bool;
-    fn GetSpirvForStage(program: *const c_void, stage: i32) -> *const c_void;
+    fn GetSpirvForStage(program: *const c_void, stage: i32, options: u64) -> *const c_void;
     fn Disassemble(spirv: *const u32, length: usize) -> *const c_char;
+    fn GetSpirv() -> *const u32;
+    fn Assemble(code: *const c_char) -> bool;
+    fn Validate(spirv: *const u32, length: usize) -> bool;
+    fn GetMessages(count: *mut usize) -> *const RawMessage;
+    fn Reflect(spirv: *const u32, length: usize) -> *const RawReflection;
+    fn Optimize(
+        spirv: *const u32,
+        length: usize,
+        level: i32,
+        passes: *const *const c_char,
+        count: usize,
+    ) -> *const u32;
     fn ClearShaderCache();
-    fn CreateBook();
-    fn AddChapter();
+    fn CreateBook(spirv: *const u32, length: usize);
+    fn AddChapter(spirv: *const u32, length: usize) -> bool;
     fn Shutdown();
 }
 
-struct Variants {
-    kws: Vec<Vec<String>>,
-}
-
-impl Variants {
-    fn new() -> Self {
-        Variants { kws: Vec::new() }
-    }
-}
-
-impl Variants {
-    fn push(&mut self, layer: Vec<String>) {
-        self.kws.push(layer);
-    }
-}
-
 struct Program {
     program: *const c_void,
     keywords: String,
@@ -94,57 +194,62 @@ struct Program {
 }
 
 struct ShaderCompiler {
-    spirv: Vec<u32>,
+    limits: ResourceLimits,
     source: String,
     csource: Option<CString>,
     programs: Vec<Program>,
     programs_free_list: Vec<usize>,
+    preambles: PreambleStack,
 }
 
 impl ShaderCompiler {
-    pub fn new() -> Self {
+    pub fn new(limits: &ResourceLimits) -> Self {
         unsafe {
-            Initialise();
+            Initialise(limits);
         }
         ShaderCompiler {
-            spirv: Vec::new(),
+            limits: *limits,
             source: String::new(),
             csource: None,
             programs: Vec::new(),
             programs_free_list: Vec::new(),
+            preambles: PreambleStack::default(),
         }
     }
 
-    pub fn reserve_keyword(kw: &str) {
+    /// Registers the keyword without adding it to a program, returning its
+    /// hash
+    pub fn reserve_keyword(kw: &str) -> Result<u64, ToggleError> {
         let cs = CString::new(kw).unwrap();
-        let csource: *const c_char = cs.as_ref().as_ptr() as *const c_char;
-        unsafe {
-            if !ReserveKeyword(csource) == 0 {
-                panic!("Unable to add keyword '{}'", kw);
-            }
+        match unsafe { ReserveKeyword(cs.as_ptr()) } {
+            0 => Err(ToggleError::Rejected(kw.to_string())),
+            hash => Ok(hash),
         }
     }
 
-    pub fn enable_global_keyword(kw: &str) {
+    pub fn enable_global_keyword(kw: &str) -> Result<(), ToggleError> {
         let cs = CString::new(kw).unwrap();
-        let csource: *const c_char = cs.as_ref().as_ptr() as *const c_char;
-        unsafe {
-            if !EnableGlobalKeyword(csource) {
-                panic!("Unable to enable keyword '{}'", kw);
-            }
+        if unsafe { EnableGlobalKeyword(cs.as_ptr()) } {
+            Ok(())
+        } else {
+            Err(ToggleError::Unregistered(kw.to_string()))
         }
     }
 
-    pub fn disable_global_keyword(kw: &str) {
+    pub fn disable_global_keyword(kw: &str) -> Result<(), ToggleError> {
+        ShaderCompiler::keyword_bit(kw).ok_or_else(|| ToggleError::Unregistered(kw.to_string()))?;
         let cs = CString::new(kw).unwrap();
-        let csource: *const c_char = cs.as_ref().as_ptr() as *const c_char;
-        unsafe {
-            if !DisableGlobalKeyword(csource) {
-                panic!("Unable to disable keyword '{}'", kw);
-            }
+        if unsafe { DisableGlobalKeyword(cs.as_ptr()) } {
+            Ok(())
+        } else {
+            Err(ToggleError::NotEnabled(kw.to_string()))
         }
     }
 
+    pub fn limits(&self) -> &ResourceLimits {
+        &self.limits
+    }
+
     pub fn load_shader(name: &str) -> String {
         fs::read_to_string(name).unwrap()
     }
@@ -152,27 +257,26 @@ impl ShaderCompiler {
 
 impl ShaderCompiler {
     pub fn create_program(&mut self) -> usize {
-        let mut handle: usize;
         let program: *const c_void;
         unsafe {
             program = CreateProgram();
         }
 
-        if self.programs_free_list.is_empty() {
-            self.programs.push(Program {
-                program,
-                keywords: String::new(),
-                linked: false,
-            });
-            handle = self.programs.len();
-        } else {
-            handle = self.programs_free_list[0];
-            if let Some(p) = self.programs_free_list.pop() {
-                self.programs_free_list[0] = p;
-                handle = p + 1;
+        let entry = Program {
+            program,
+            keywords: String::new(),
+            linked: false,
+        };
+        match self.programs_free_list.pop() {
+            Some(index) => {
+                self.programs[index] = entry;
+                index + 1
+            }
+            None => {
+                self.programs.push(entry);
+                self.programs.len()
             }
         }
-        handle
     }
 
     pub fn delete_program(&mut self, handle: usize) {
@@ -190,20 +294,26 @@ impl ShaderCompiler {
         }
     }
 
-    pub fn compile(&mut self, stage: Stage, source: &str) -> usize {
+    pub fn compile(&mut self, stage: Stage, source: &str, options: &CompileOptions) -> usize {
         self.csource = Some(CString::new(source).unwrap());
         let csource: *const c_char = self.csource.as_ref().unwrap().as_ptr() as *const c_char;
         let handle: usize;
+        self.sync_preamble();
         unsafe {
-            handle = CompileShader(stage as i32, csource);
+            handle = CompileShader(stage as i32, csource, options.bits());
         }
         handle
     }
 
-    pub fn compile_from_file(&mut self, stage: Stage, name: &str) -> usize {
+    pub fn compile_from_file(
+        &mut self,
+        stage: Stage,
+        name: &str,
+        options: &CompileOptions,
+    ) -> usize {
         println!("Reading File: '{}'", name);
         match read_to_string(name) {
-            Ok(f) => self.compile(stage, &f),
+            Ok(f) => self.compile(stage, &f, options),
             Err(_) => {
                 println!("Error: cannot read file '{}'!", name);
                 0
@@ -211,77 +321,298 @@ impl ShaderCompiler {
         }
     }
 
-    pub fn enable_keyword(&self, kw: &str) {
+    pub fn enable_keyword(&self, kw: &str) -> Result<(), ToggleError> {
         let cs = CString::new(kw).unwrap();
-        let csource: *const c_char = cs.as_ref().as_ptr() as *const c_char;
-        unsafe {
-            if !EnableKeyword(csource) {
-                panic!("Unable to enable keyword '{}'", kw);
-            }
+        if unsafe { EnableKeyword(cs.as_ptr()) } {
+            Ok(())
+        } else {
+            Err(ToggleError::Unregistered(kw.to_string()))
         }
     }
 
-    pub fn disable_keyword(&self, kw: &str) {
+    pub fn disable_keyword(&self, kw: &str) -> Result<(), ToggleError> {
+        ShaderCompiler::keyword_bit(kw).ok_or_else(|| ToggleError::Unregistered(kw.to_string()))?;
         let cs = CString::new(kw).unwrap();
-        let csource: *const c_char = cs.as_ref().as_ptr() as *const c_char;
-        unsafe {
-            if !DisableKeyword(csource) {
-                panic!("Unable to disable keyword '{}'", kw);
-            }
+        if unsafe { DisableKeyword(cs.as_ptr()) } {
+            Ok(())
+        } else {
+            Err(ToggleError::NotEnabled(kw.to_string()))
         }
     }
 
+    /// Bit of a registered keyword in a `KeywordMask`
+    pub fn keyword_bit(kw: &str) -> Option<u8> {
+        let cs = CString::new(kw).unwrap();
+        let bit = unsafe { KeywordBit(cs.as_ptr()) };
+        u8::try_from(bit).ok()
+    }
+
+    pub fn keyword_bit_from_hash(hash: u64) -> Option<u8> {
+        u8::try_from(unsafe { KeywordBitFromHash(hash) }).ok()
+    }
+
+    pub fn keyword_hash_from_bit(bit: u8) -> Option<u64> {
+        match unsafe { KeywordHashFromBit(bit as i32) } {
+            0 => None,
+            hash => Some(hash),
+        }
+    }
+
+    /// Mask of registered keywords, failing with the first one that isn't
+    pub fn keyword_mask<S: AsRef<str>>(keywords: &[S]) -> Result<KeywordMask, ToggleError> {
+        let mut mask = KeywordMask::new();
+        for kw in keywords {
+            let kw = kw.as_ref();
+            let bit = ShaderCompiler::keyword_bit(kw)
+                .ok_or_else(|| ToggleError::Unregistered(kw.to_string()))?;
+            mask.enable(bit);
+        }
+        Ok(mask)
+    }
+
+    /// Keywords enabled on this thread
+    pub fn enabled_keywords(&self) -> KeywordMask {
+        ShaderCompiler::thread_enabled_keywords()
+    }
+
+    /// Keywords enabled on the calling thread, without a compiler, so they
+    /// can be handed to compilers on other threads
+    pub fn thread_enabled_keywords() -> KeywordMask {
+        let mut mask = KeywordMask::new();
+        unsafe { GetEnabledKeywords(&mut mask) };
+        mask
+    }
+
+    /// Replaces this thread's enabled keywords in one step
+    pub fn set_enabled_keywords(&self, mask: &KeywordMask) {
+        unsafe { SetEnabledKeywords(mask) }
+    }
+
+    pub fn global_enabled_keywords() -> KeywordMask {
+        let mut mask = KeywordMask::new();
+        unsafe { GetGlobalEnabledKeywords(&mut mask) };
+        mask
+    }
+
+    pub fn set_global_enabled_keywords(mask: &KeywordMask) {
+        unsafe { SetGlobalEnabledKeywords(mask) }
+    }
+
+    /// Enables the keywords on this thread, returning the earlier set
+    pub fn enable_keywords(&self, mask: &KeywordMask) -> KeywordMask {
+        let mut previous = KeywordMask::new();
+        unsafe { EnableKeywords(mask, &mut previous) };
+        previous
+    }
+
+    pub fn disable_keywords(&self, mask: &KeywordMask) {
+        unsafe { DisableKeywords(mask) }
+    }
+
+    /// Enables the keywords on every thread, returning the earlier set
+    pub fn enable_global_keywords(mask: &KeywordMask) -> KeywordMask {
+        let mut previous = KeywordMask::new();
+        unsafe { EnableGlobalKeywords(mask, &mut previous) };
+        previous
+    }
+
+    pub fn disable_global_keywords(mask: &KeywordMask) {
+        unsafe { DisableGlobalKeywords(mask) }
+    }
+
+    /// Enables the keywords on this thread until the scope is dropped. Crate
+    /// visible like `KeywordScope`, which derefs to the compiler
+    pub(crate) fn keyword_scope<S: AsRef<str>>(
+        &mut self,
+        keywords: &[S],
+    ) -> Result<KeywordScope<'_>, ToggleError> {
+        let mask = ShaderCompiler::keyword_mask(keywords)?;
+        Ok(KeywordScope::new(self, &mask))
+    }
+
+    /// Enables the keywords on every thread until the scope is dropped
+    pub fn global_keyword_scope<S: AsRef<str>>(
+        keywords: &[S],
+    ) -> Result<GlobalKeywordScope, ToggleError> {
+        let mask = ShaderCompiler::keyword_mask(keywords)?;
+        Ok(GlobalKeywordScope::new(&mask))
+    }
+
+    /// Runs `f` with the keywords enabled on this thread, disabling them again
+    /// afterwards even if `f` panics
+    pub fn with_keywords<S: AsRef<str>, R>(
+        &mut self,
+        keywords: &[S],
+        f: impl FnOnce(&mut ShaderCompiler) -> R,
+    ) -> Result<R, ToggleError> {
+        let mut scope = self.keyword_scope(keywords)?;
+        Ok(f(&mut scope))
+    }
+
+    pub fn with_global_keywords<S: AsRef<str>, R>(
+        &mut self,
+        keywords: &[S],
+        f: impl FnOnce(&mut ShaderCompiler) -> R,
+    ) -> Result<R, ToggleError> {
+        let _scope = ShaderCompiler::global_keyword_scope(keywords)?;
+        Ok(f(self))
+    }
+
     pub fn get_keywords_id(&self) -> u64 {
         unsafe { GetKeywordsID() }
     }
 
-    pub fn add_keyword(&mut self, program: usize, kw: &str) {
+    /// Adds the keyword to the program about to be compiled, returning its
+    /// hash. Fails on a hash collision, or once the program's stages have
+    /// been compiled.
+    pub fn add_keyword(&mut self, program: usize, kw: &str) -> Result<u64, ToggleError> {
         self.csource = Some(CString::new(kw).unwrap());
         let csource: *const c_char = self.csource.as_ref().unwrap().as_ptr() as *const c_char;
-        unsafe {
-            if !AddKeyword(csource) == 0 {
-                panic!("Unable to add keyword '{}'", kw);
-            }
+        let hash = unsafe { AddKeyword(csource) };
+        if hash == 0 {
+            return Err(ToggleError::Rejected(kw.to_string()));
         }
         let index = program - 1;
         self.programs[index].keywords.push_str(kw);
         self.programs[index].keywords.push('\n');
+        Ok(hash)
     }
 
-    pub fn set_preamble(&mut self, source: &str) {
-        self.csource = Some(CString::new(source).unwrap());
-        let csource: *const c_char = self.csource.as_ref().unwrap().as_ptr() as *const c_char;
+    /// Directory searched by `#include`, after the including file's own
+    pub fn add_include_dir(&mut self, dir: &str) {
+        let cs = CString::new(dir).unwrap();
+        unsafe {
+            AddIncludeDir(cs.as_ptr());
+        }
+    }
+
+    /// In-memory file for `#include "name"` or `#include <name>`, found before
+    /// anything on disk
+    pub fn add_virtual_include(&mut self, name: &str, contents: &str) {
+        let cs_name = CString::new(name).unwrap();
+        let cs_contents = CString::new(contents).unwrap();
         unsafe {
-            SetPreamble(csource);
+            AddVirtualInclude(cs_name.as_ptr(), cs_contents.as_ptr());
         }
     }
 
-    pub fn clear_preamble(&self) {
+    /// Files the compiled shader included, virtual ones by name and the rest
+    /// by the path they were found at
+    pub fn included_files(&self, handle: usize) -> Vec<String> {
+        let mut count = 0;
         unsafe {
-            ClearPreamble();
+            let names = GetIncludedFiles(handle, &mut count);
+            reflect::slice(names, count)
+                .iter()
+                .map(|&name| reflect::name(name))
+                .collect()
         }
     }
 
+    /// Replaces the global preamble, which stays until cleared
+    pub fn set_preamble(&mut self, source: &str) {
+        self.preambles.clear(PreambleScope::Global);
+        self.preambles.push(PreambleScope::Global, source);
+    }
+
+    /// Drops every preamble in every scope
+    pub fn clear_preamble(&mut self) {
+        for scope in [
+            PreambleScope::Global,
+            PreambleScope::Shader,
+            PreambleScope::Variant,
+        ] {
+            self.preambles.clear(scope);
+        }
+    }
+
+    /// Adds a preamble for as long as the guard lives
+    pub fn push_preamble(&mut self, scope: PreambleScope, source: &str) -> PreambleGuard<'_> {
+        let id = self.preambles.push(scope, source);
+        PreambleGuard::new(self, id)
+    }
+
+    pub fn preamble(&self) -> String {
+        self.preambles.compose()
+    }
+
+    // Hands the composed stack to glslang, keyword defines are added after it
+    fn sync_preamble(&self) {
+        let preamble = self.preambles.compose();
+        unsafe {
+            if preamble.is_empty() {
+                ClearPreamble();
+            } else {
+                let cs = CString::new(preamble).unwrap();
+                SetPreamble(cs.as_ptr());
+            }
+        }
+    }
+
+    /// Recompiles with the current preamble stack
     pub fn recompile(&self, handle: usize) -> usize {
+        self.sync_preamble();
         unsafe { Recompile(handle) }
     }
 
-    pub fn get_spirv_for_stage(&mut self, program: usize, stage: Stage) {
+    pub fn get_spirv_for_stage(
+        &mut self,
+        program: usize,
+        stage: Stage,
+        options: &CompileOptions,
+    ) -> Result<SpirvModule, SpirvError> {
         let index = program - 1;
+        if !self.programs[index].linked {
+            return Err(SpirvError::MissingStage(stage));
+        }
         unsafe {
-            let ptr = GetSpirvForStage(self.programs[index].program, stage as i32);
-            if ptr != null() && self.programs[index].linked {
-                let size = GetSpirvSize();
-                self.spirv.resize_with(size, Default::default);
-                copy(ptr as *mut u32, self.spirv.as_mut_ptr(), size);
-            } else {
-                self.spirv.clear();
+            let ptr = GetSpirvForStage(self.programs[index].program, stage as i32, options.bits());
+            if ptr.is_null() {
+                return Err(SpirvError::MissingStage(stage));
             }
+            SpirvModule::new(copy_spirv(ptr as *const u32))
         }
     }
 
-    pub fn get_spirv_size(&self) -> usize {
-        self.spirv.len()
+    /// Optimises the module in place.
+    pub fn optimize(&self, module: &mut SpirvModule, level: &OptLevel) -> Option<OptReport> {
+        let flags: Vec<CString> = level
+            .flags()
+            .into_iter()
+            .map(|f| CString::new(f).unwrap())
+            .collect();
+        let passes: Vec<*const c_char> = flags.iter().map(|f| f.as_ptr()).collect();
+        let before = module.words().len();
+        let optimized = unsafe {
+            let ptr = Optimize(
+                module.words().as_ptr(),
+                before,
+                level.preset(),
+                passes.as_ptr(),
+                passes.len(),
+            );
+            if ptr.is_null() {
+                return None;
+            }
+            copy_spirv(ptr)
+        };
+        *module = SpirvModule::new(optimized).ok()?;
+        Some(OptReport {
+            before,
+            after: module.words().len(),
+        })
+    }
+
+    pub fn optimize_stage(
+        &mut self,
+        program: usize,
+        stage: Stage,
+        options: &CompileOptions,
+        level: &OptLevel,
+    ) -> Option<(SpirvModule, OptReport)> {
+        let mut module = self.get_spirv_for_stage(program, stage, options).ok()?;
+        let report = self.optimize(&mut module, level)?;
+        Some((module, report))
     }
 
     pub fn add(&mut self, program: usize, handle: usize) {
@@ -299,74 +630,163 @@ impl ShaderCompiler {
         }
     }
 
-    pub fn save_spirv(&self, dest: &mut Vec<u32>) {
-        *dest = self.spirv.clone()
+    pub fn write_spirv(&self, module: &SpirvModule, name: &str) -> Result<(), SpirvError> {
+        module.write(name)
     }
 
-    pub fn write_spirv(&mut self, name: &str) {
-        let mut file = File::create(name).unwrap();
-        unsafe {
-            let current_length = self.spirv.len();
-            self.spirv
-                .set_len(current_length * std::mem::size_of::<u32>());
-            let slice: &[u8] = std::mem::transmute::<&[u32], &[u8]>(self.spirv.as_slice());
-            file.write(slice).unwrap();
-            self.spirv.set_len(current_length);
-        }
+    pub fn read_spirv(&self, name: &str) -> Result<SpirvModule, SpirvError> {
+        SpirvModule::read(name)
     }
 
-    pub fn read_spirv(&mut self, name: &str) {
-        let meta = fs::metadata(name).unwrap();
-        let size = meta.len() as usize;
-        let mut file = File::open(name).unwrap();
-        let u32size = size / std::mem::size_of::<u32>();
-        unsafe {
-            self.spirv.resize_with(u32size, Default::default);
-            self.spirv.set_len(size);
-            let slice: &mut [u8] =
-                std::mem::transmute::<&mut [u32], &mut [u8]>(self.spirv.as_mut_slice());
-            file.read(slice).unwrap();
-            self.spirv.set_len(u32size);
-        }
+    /// Sorted keywords of a key recorded in `keys`, such as the
+    /// variants/keys.json a build writes
+    pub fn get_keywords_from_id(&self, key: VariantKey, keys: &VariantKeys) -> Option<Vec<String>> {
+        keys.keywords(key).map(|k| k.to_vec())
+    }
+
+    /// Stable key of the keyword set the last compile used, including the
+    /// enabled keywords
+    pub fn get_variant_key(&self) -> Result<VariantKey, VariantKeyError> {
+        self.get_variant_keywords().map(|k| VariantKey::new(&k))
     }
 
-    pub fn get_keywords_from_id(&mut self, id: u64) -> String {
-        let kw: *const c_char;
+    /// Sorted keyword set the last compile used, the enabled keywords
+    /// included, which `get_variant_key` is made from
+    pub fn get_variant_keywords(&self) -> Result<Vec<String>, VariantKeyError> {
+        let id = self.get_keywords_id();
         unsafe {
-            kw = GetKeywordsFromID(id);
-            let c_str: &CStr = CStr::from_ptr(kw);
-            let str_slice: &str = c_str.to_str().unwrap();
-            str_slice.to_owned()
+            if KeywordsCollided() {
+                return Err(VariantKeyError::Combination(id));
+            }
+        }
+        let mut keywords = Vec::new();
+        if id != 0 {
+            unsafe {
+                let kw = CStr::from_ptr(GetKeywordsFromID(id));
+                keywords.extend(kw.to_str().unwrap().lines().map(|k| k.to_string()));
+            }
         }
+        Ok(VariantKey::canonical(&keywords))
     }
 
-    pub fn disassemble_spirv(&mut self) -> String {
-        let length = self.spirv.len();
+    pub fn disassemble_spirv(&self, module: &SpirvModule) -> String {
         let source: *const c_char;
         unsafe {
-            source = Disassemble(self.spirv.as_ptr(), length);
+            source = Disassemble(module.words().as_ptr(), module.words().len());
             let c_str: &CStr = CStr::from_ptr(source);
             let str_slice: &str = c_str.to_str().unwrap();
             str_slice.to_owned()
         }
     }
 
-    pub fn decompile_spirv(&self, gapi: GraphicsAPI) -> String {
+    /// Takes a `SpirvModule` or bare words, so hand-patched or broken SPIR-V
+    /// that `SpirvModule::new` would refuse can still be validated
+    pub fn validate<S: AsRef<[u32]> + ?Sized>(
+        &self,
+        spirv: &S,
+    ) -> Result<(), Vec<ValidationMessage>> {
+        let words = spirv.as_ref();
+        unsafe {
+            let valid = Validate(words.as_ptr(), words.len());
+            let mut count = 0;
+            let messages = validation::collect(GetMessages(&mut count), count);
+            if valid {
+                Ok(())
+            } else {
+                Err(messages)
+            }
+        }
+    }
+
+    pub fn assemble(&self, text: &str) -> Result<Vec<u32>, Vec<ValidationMessage>> {
+        let cs = CString::new(text).unwrap();
+        unsafe {
+            let assembled = Assemble(cs.as_ptr());
+            let mut count = 0;
+            let messages = validation::collect(GetMessages(&mut count), count);
+            if !assembled {
+                return Err(messages);
+            }
+            Ok(copy_spirv(GetSpirv()))
+        }
+    }
+
+    pub fn reflect(&self, module: &SpirvModule) -> Option<ShaderReflection> {
+        unsafe {
+            let raw = Reflect(module.words().as_ptr(), module.words().len());
+            if raw.is_null() {
+                return None;
+            }
+            Some(reflect::collect(&*raw))
+        }
+    }
+
+    // GLSL and reflection keep the Vulkan bindings, the remap table only
+    // applies to HLSL and Metal
+    pub fn decompile_spirv(
+        &self,
+        module: &SpirvModule,
+        gapi: &GraphicsAPI,
+        remap: &BindingRemap,
+    ) -> Option<CrossCompiled> {
+        let spirv = module.words();
+        let slots = remap.slots();
         let source: *const c_char;
+        let target: &str;
         unsafe {
             match gapi {
-                GraphicsAPI::GLSL => source = DecompileToGLSL(),
-                GraphicsAPI::HLSL => source = DecompileToHLSL(),
-                GraphicsAPI::Metal => source = DecompileToMetal(),
+                GraphicsAPI::GLSL(options) => {
+                    source = DecompileToGLSL(spirv.as_ptr(), spirv.len(), options);
+                    target = "glsl";
+                }
+                GraphicsAPI::HLSL(options) => {
+                    source = DecompileToHLSL(
+                        spirv.as_ptr(),
+                        spirv.len(),
+                        options,
+                        slots.as_ptr(),
+                        slots.len(),
+                    );
+                    target = "hlsl";
+                }
+                GraphicsAPI::Metal(options) => {
+                    source = DecompileToMetal(
+                        spirv.as_ptr(),
+                        spirv.len(),
+                        options,
+                        slots.as_ptr(),
+                        slots.len(),
+                    );
+                    target = "msl";
+                }
+                GraphicsAPI::Reflection => {
+                    source = DecompileToReflection(spirv.as_ptr(), spirv.len());
+                    target = "json";
+                }
             };
+            if source.is_null() {
+                return None;
+            }
             let c_str: &CStr = CStr::from_ptr(source);
             let str_slice: &str = c_str.to_str().unwrap();
-            str_slice.to_owned()
+
+            let mut count = 0;
+            let raw = GetBindingManifest(&mut count);
+            let mut manifest = BindingManifest::collect(target, raw, count);
+            if let GraphicsAPI::GLSL(options) = gapi {
+                if options.gles_compat {
+                    manifest.add_gles_compat(&*GetGlesCompat());
+                }
+            }
+            Some(CrossCompiled {
+                source: str_slice.to_owned(),
+                manifest,
+            })
         }
     }
 
-    pub fn print_spirv(&self) {
-        unsafe { PrintSpirv() }
+    pub fn print_spirv(&self, module: &SpirvModule) {
+        unsafe { PrintSpirv(module.words().as_ptr(), module.words().len()) }
     }
 
     pub fn print_keywords(&self) {
@@ -375,19 +795,57 @@ impl ShaderCompiler {
         }
     }
 
-    pub fn create_book(&self) {
+    /// The keyword registry shared by all threads, in registration order
+    pub fn registered_keywords(&self) -> Vec<RegisteredKeyword> {
+        let mut count = 0;
+        unsafe { registry::collect(ListKeywords(&mut count), count) }
+    }
+
+    pub fn create_book(&self, module: &SpirvModule) {
         unsafe {
-            CreateBook();
+            CreateBook(module.words().as_ptr(), module.words().len());
         }
     }
 
-    pub fn add_chapter(&self) {
+    pub fn add_chapter(&self, module: &SpirvModule) {
         unsafe {
-            AddChapter();
+            AddChapter(module.words().as_ptr(), module.words().len());
         }
     }
 }
 
+#[cfg(test)]
+impl ShaderCompiler {
+    /// Compiles and links a single stage with the default options
+    pub(crate) fn compile_stage(&mut self, stage: Stage, source: &str) -> SpirvModule {
+        let options = CompileOptions::default();
+        let program = self.create_program();
+        let handle = self.compile(stage, source, &options);
+        assert_ne!(handle, 0, "{} stage failed to compile", stage.extension());
+        self.add(program, handle);
+        assert!(
+            self.link(program),
+            "{} stage failed to link",
+            stage.extension()
+        );
+        self.get_spirv_for_stage(program, stage, &options).unwrap()
+    }
+
+    /// Whether the thread accepts `hashes` as the keyword list of the
+    /// combination `hash`, as a compile with those keywords would
+    pub(crate) fn authenticate_combination(&self, hash: u64, hashes: &[u64]) -> bool {
+        unsafe { AuthenticateCombination(hash, hashes.as_ptr(), hashes.len()) }
+    }
+}
+
+// Copies the thread's C++ spirv buffer that ptr points into
+unsafe fn copy_spirv(ptr: *const u32) -> Vec<u32> {
+    let size = GetSpirvSize();
+    let mut spirv: Vec<u32> = vec![0; size];
+    copy(ptr, spirv.as_mut_ptr(), size);
+    spirv
+}
+
 impl Drop for ShaderCompiler {
     fn drop(&mut self) {
         unsafe {
@@ -402,8 +860,39 @@ impl Drop for ShaderCompiler {
     }
 }
 
+// Value following `name` on the command line
+fn arg_value(args: &[String], name: &str) -> Option<String> {
+    let index = args.iter().position(|a| a == name)?;
+    args.get(index + 1).cloned()
+}
+
 fn main() {
     println!("Hello, world!");
+    let args: Vec<String> = std::env::args().collect();
+    // `--limits` takes a preset name or a TOML or JSON device profile
+    let limits = match arg_value(&args, "--limits") {
+        Some(profile) => match ResourceLimits::load(&profile) {
+            Ok(limits) => limits,
+            Err(e) => {
+                println!("{}", e);
+                std::process::exit(1);
+            }
+        },
+        None => ResourceLimits::default(),
+    };
+    // `--opt` takes none, performance, size or a comma separated list of
+    // spirv-opt flags
+    let opt = match arg_value(&args, "--opt") {
+        Some(level) => match OptLevel::parse(&level) {
+            Some(level) => level,
+            None => {
+                println!("Unknown optimisation level '{}'", level);
+                std::process::exit(1);
+            }
+        },
+        None => OptLevel::None,
+    };
+
     unsafe {
         if glfwInit() == GLFW_TRUE {
             println!("GLFW Initialised OK!");
@@ -414,65 +903,257 @@ fn main() {
         }
         glfwTerminate();
 
-        let _shader = Parser::new("src/shaders/deferredwithtransparent.esl");
+        let shader = Parser::new("src/shaders/deferredwithtransparent.esl");
 
-        let mut variants = Variants::new();
-        variants.push(vec!["NOT_LIT".to_string(), "LIT".to_string()]);
-        variants.push(vec!["NOT_LOD_BIAS".to_string(), "LOD_BIAS".to_string()]);
+        let mut space = KeywordSpace::new();
+        space
+            .add_axis("lighting", &["_", "LIT"])
+            .and_then(|s| s.add_axis("lod", &["_", "LOD_BIAS"]))
+            .and_then(|s| s.parse_constraint("LOD_BIAS requires LIT"))
+            .unwrap();
 
-        create_variants(&variants);
+        // Only the variants the game recorded, when it has recorded some
+        let collection = match VariantCollection::load("collection.json") {
+            Ok(collection) => Some(collection),
+            Err(CollectionError::Io(e)) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(e) => {
+                println!("{}", e);
+                None
+            }
+        };
+
+        // The stage files stand in for the first pass of the parsed shader,
+        // whose names the game records its variants under
+        let collected = match (&collection, shader.name(), shader.pass_names().first()) {
+            (Some(collection), Some(name), Some(pass)) => Some((collection, name, pass.as_str())),
+            (Some(_), _, _) => {
+                println!("The shader has no name or pass to look up collected variants by");
+                None
+            }
+            _ => None,
+        };
+
+        let stages = [
+            (Stage::VertexStage, "test.vert"),
+            (Stage::FragmentStage, "test.frag"),
+        ];
+        if let Err(e) = create_variants(&stages, &space, collected, &limits, &opt) {
+            println!("Variant build failed: {}", e);
+            std::process::exit(1);
+        }
     }
 }
 
-fn create_variants(variants: &Variants) {
-    let mut compiler = ShaderCompiler::new();
-    let p = compiler.create_program();
+// Builds the variants of the shader made of the stage files, or only those
+// collected for the given shader and pass. Fails if a keyword collides with one
+// from the project's history.
+fn create_variants(
+    stages: &[(Stage, &str)],
+    space: &KeywordSpace,
+    collected: Option<(&VariantCollection, &str, &str)>,
+    limits: &ResourceLimits,
+    opt: &OptLevel,
+) -> Result<(), RegistryError> {
+    let mut compiler = ShaderCompiler::new(limits);
+    let options = CompileOptions::default();
+    fs::create_dir_all("variants").unwrap();
 
-    let v = compiler.compile_from_file(Stage::VertexStage, "test.vert");
-    let f = compiler.compile_from_file(Stage::FragmentStage, "test.frag");
-    compiler.add(p, v);
-    compiler.add(p, f);
-    compiler.link(p);
+    let sources: Vec<(Stage, &str, String)> = stages
+        .iter()
+        .map(|&(stage, path)| (stage, path, ShaderCompiler::load_shader(path)))
+        .collect();
+    let tested: Vec<(&str, BTreeSet<String>)> = sources
+        .iter()
+        .map(|(_, path, source)| (*path, lint::tested_macros(source)))
+        .collect();
+    // Keywords are recorded against the first stage file that tests them,
+    // valued keywords such as QUALITY=2 by their name
+    let first_seen = |keyword: &str| -> String {
+        let name = keyword.split('=').next().unwrap_or(keyword);
+        let path = tested.iter().find(|(_, macros)| macros.contains(name));
+        path.map_or(stages[0].1, |(path, _)| *path).to_string()
+    };
 
-    let rows = variants.kws.len();
-    let mut row_indices: Vec<usize> = Vec::with_capacity(rows);
+    // Check the keywords against every earlier build before compiling. A
+    // missing registry starts empty, an unreadable one fails the build rather
+    // than skip the check
+    let mut registry = KeywordRegistry::load("keywords.json")?;
+    let keywords: Vec<&String> = space
+        .axes()
+        .iter()
+        .flat_map(|axis| axis.keywords().iter().flatten())
+        .collect();
+    registry.check_all(&keywords)?;
+    for keyword in keywords {
+        registry.register(keyword, &first_seen(keyword))?;
+    }
 
-    for _ in 0..rows {
-        row_indices.push(0);
+    let mut builder = VariantBuilder::new().limits(limits).options(&options);
+    for (stage, _, source) in &sources {
+        builder = builder.stage(*stage, source);
+    }
+    let results = match collected {
+        Some((collection, shader, pass)) => {
+            builder.build_collected(space, collection, shader, pass)
+        }
+        None => builder.build(space),
+    };
+
+    // Every key the build made, so archives indexed by them can be read back.
+    // Failed variants have no archive entry to read
+    let mut keys = VariantKeys::new();
+    for result in results.iter().filter(|r| r.linked) {
+        if let Err(e) = keys.insert(&result.all_keywords) {
+            println!("{}", e);
+        }
+    }
+    if let Err(e) = keys.save("variants/keys.json") {
+        println!("{}", e);
     }
 
-    let mut done = false;
-    let mut count = 0;
-    while !done {
-        let np = compiler.create_program();
-        for r in 0..rows {
-            compiler.add_keyword(p, &variants.kws[r][row_indices[r]]);
+    let mut blocks = Vec::new();
+    for result in &results {
+        println!("Keywords:\n{}", result.keywords.join("\n"));
+        if result.linked {
+            for reflection in write_variant(&mut compiler, result, opt) {
+                blocks.push((result.key, reflection));
+            }
         }
-        let nv = compiler.recompile(v);
-        let nf = compiler.recompile(f);
+    }
 
-        compiler.add(np, nv);
-        compiler.add(np, nf);
-        compiler.link(np);
+    fs::write(
+        "variants/blocks.rs",
+        codegen::generate_block_structs(&blocks),
+    )
+    .unwrap();
+    compiler.print_keywords();
+    for keyword in compiler.registered_keywords() {
+        registry.register(&keyword.name, &first_seen(&keyword.name))?;
+    }
+    registry.save("keywords.json")?;
+    println!("Variants count: {}", results.len());
 
+    // LOD_BIAS on its own is pruned by its constraint, so this falls back
+    let selector = VariantSelector::from_results(&results);
+    if let Some(selection) = selector.select(&["LOD_BIAS"]) {
         println!(
-            "Keywords:\n{}",
-            compiler.get_keywords_from_id(compiler.get_keywords_id())
+            "Selected variant {} for [LOD_BIAS], unsatisfied: [{}]",
+            selection.key,
+            selection.unsatisfied.join(", ")
         );
+    }
+    Ok(())
+}
 
-        count += 1;
-        for r in (0..rows).rev() {
-            row_indices[r] += 1;
-            if row_indices[r] != variants.kws[r].len() {
-                break;
+// Optimises and validates each stage of a compiled variant and writes the ones
+// that pass to the variants directory, returning their reflection
+fn write_variant(
+    compiler: &mut ShaderCompiler,
+    result: &VariantResult,
+    opt: &OptLevel,
+) -> Vec<ShaderReflection> {
+    let mut reflections = Vec::new();
+    for (stage, module) in &result.stages {
+        let mut module = module.clone();
+        if *opt != OptLevel::None {
+            match compiler.optimize(&mut module, opt) {
+                Some(report) => println!(
+                    "Optimised variant {} {} stage: {}",
+                    result.key,
+                    stage.extension(),
+                    report
+                ),
+                None => println!(
+                    "Variant {} {} stage failed to optimise, writing it unoptimised",
+                    result.key,
+                    stage.extension()
+                ),
             }
-            row_indices[r] = 0;
-            if r == 0 {
-                done = true;
-                break;
+        }
+        match compiler.validate(&module) {
+            Ok(()) => {
+                let name = format!("variants/{}.{}.spv", result.key, stage.extension());
+                if let Err(e) = compiler.write_spirv(&module, &name) {
+                    println!("Cannot write '{}': {}", name, e);
+                }
+                if let Some(reflection) = compiler.reflect(&module) {
+                    reflections.push(reflection);
+                }
+                let json = compiler.decompile_spirv(
+                    &module,
+                    &GraphicsAPI::Reflection,
+                    &BindingRemap::new(),
+                );
+                if let Some(json) = json {
+                    let name = format!("variants/{}.{}.json", result.key, stage.extension());
+                    if let Err(e) = fs::write(&name, json.source) {
+                        println!("Cannot write '{}': {}", name, e);
+                    }
+                }
+            }
+            Err(messages) => {
+                println!(
+                    "Variant {} {} stage failed validation:",
+                    result.key,
+                    stage.extension()
+                );
+                for m in messages {
+                    println!("  {}", m);
+                }
             }
         }
     }
-    compiler.print_keywords();
-    println!("Variants count: {}", count);
+    reflections
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INCLUDING: &str = "#version 450
+#include \"common.glsl\"
+#include <lib/extra.glsl>
+layout(location = 0) out vec4 colour;
+void main() {
+    colour = vec4(FROM_VIRTUAL, FROM_DISK, 0.0, 1.0);
+}
+";
+
+    #[test]
+    fn virtual_includes_come_before_include_dirs() {
+        let dir = std::env::temp_dir().join(format!("includes-{}", std::process::id()));
+        fs::create_dir_all(dir.join("lib")).unwrap();
+        fs::write(
+            dir.join("common.glsl"),
+            "#error the disk copy was included\n",
+        )
+        .unwrap();
+        fs::write(dir.join("lib/extra.glsl"), "#define FROM_DISK 2.0\n").unwrap();
+
+        let mut compiler = ShaderCompiler::new(&ResourceLimits::default());
+        compiler.add_include_dir(dir.to_str().unwrap());
+        compiler.add_virtual_include("common.glsl", "#define FROM_VIRTUAL 1.0\n");
+        let program = compiler.create_program();
+        let handle = compiler.compile(Stage::FragmentStage, INCLUDING, &CompileOptions::default());
+        let included = compiler.included_files(handle);
+        compiler.delete_program(program);
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_ne!(handle, 0);
+        assert_eq!(included.len(), 2);
+        assert_eq!(included[0], "common.glsl");
+        assert!(included[1].ends_with("extra.glsl"), "{}", included[1]);
+    }
+
+    #[test]
+    fn combinations_sharing_a_hash_must_share_keywords() {
+        let compiler = ShaderCompiler::new(&ResourceLimits::default());
+        assert!(compiler.authenticate_combination(0x5eed, &[1, 2]));
+        assert!(compiler.authenticate_combination(0x5eed, &[1, 2]));
+        assert!(!compiler.authenticate_combination(0x5eed, &[1, 3]));
+        // Lists of another length used to be recorded as a second combination
+        assert!(!compiler.authenticate_combination(0x5eed, &[1, 2, 3]));
+        assert!(!compiler.authenticate_combination(0x5eed, &[1]));
+        assert!(compiler.authenticate_combination(0xfeed, &[1, 2, 3]));
+    }
 }
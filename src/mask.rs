@@ -0,0 +1,170 @@
+use std::ops::{BitAnd, BitAndAssign, BitOr, BitOrAssign};
+
+/// The most keywords the compiler's registry holds
+pub const MAX_KEYWORDS: usize = 256;
+
+/// Set of registered keywords, one bit each by registration order, the same
+/// layout as `KeywordMask` in keywords.h. Enabling, disabling and combining
+/// sets are single bit operations, so renderers can switch keywords every
+/// frame.
+///
+/// Bits come from `ShaderCompiler::keyword_bit`, they're only meaningful in
+/// the process that registered the keywords.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct KeywordMask {
+    bits: [u64; MAX_KEYWORDS / 64],
+}
+
+impl KeywordMask {
+    pub fn new() -> Self {
+        KeywordMask::default()
+    }
+
+    pub fn enable(&mut self, bit: u8) -> &mut Self {
+        self.bits[bit as usize >> 6] |= 1 << (bit & 63);
+        self
+    }
+
+    pub fn disable(&mut self, bit: u8) -> &mut Self {
+        self.bits[bit as usize >> 6] &= !(1 << (bit & 63));
+        self
+    }
+
+    pub fn contains(&self, bit: u8) -> bool {
+        self.bits[bit as usize >> 6] >> (bit & 63) & 1 != 0
+    }
+
+    pub fn union(&self, other: &KeywordMask) -> KeywordMask {
+        *self | *other
+    }
+
+    pub fn intersection(&self, other: &KeywordMask) -> KeywordMask {
+        *self & *other
+    }
+
+    /// Bits of this mask that aren't in `other`
+    pub fn difference(&self, other: &KeywordMask) -> KeywordMask {
+        let mut mask = *self;
+        for (word, other) in mask.bits.iter_mut().zip(&other.bits) {
+            *word &= !other;
+        }
+        mask
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bits.iter().all(|&word| word == 0)
+    }
+
+    pub fn len(&self) -> usize {
+        self.bits
+            .iter()
+            .map(|word| word.count_ones() as usize)
+            .sum()
+    }
+
+    /// Enabled bits in ascending order
+    pub fn iter(&self) -> impl Iterator<Item = u8> + '_ {
+        (0..MAX_KEYWORDS)
+            .map(|bit| bit as u8)
+            .filter(move |&bit| self.contains(bit))
+    }
+
+    /// Mask of registered keyword hashes, failing with the first hash that
+    /// isn't registered
+    pub fn from_hashes(hashes: &[u64]) -> Result<Self, u64> {
+        let mut mask = KeywordMask::new();
+        for &hash in hashes {
+            let bit = crate::ShaderCompiler::keyword_bit_from_hash(hash).ok_or(hash)?;
+            mask.enable(bit);
+        }
+        Ok(mask)
+    }
+
+    /// Keyword hashes of the enabled bits
+    pub fn to_hashes(self) -> Vec<u64> {
+        self.iter()
+            .filter_map(crate::ShaderCompiler::keyword_hash_from_bit)
+            .collect()
+    }
+}
+
+impl BitOr for KeywordMask {
+    type Output = KeywordMask;
+
+    fn bitor(mut self, other: KeywordMask) -> KeywordMask {
+        self |= other;
+        self
+    }
+}
+
+impl BitOrAssign for KeywordMask {
+    fn bitor_assign(&mut self, other: KeywordMask) {
+        for (word, other) in self.bits.iter_mut().zip(&other.bits) {
+            *word |= other;
+        }
+    }
+}
+
+impl BitAnd for KeywordMask {
+    type Output = KeywordMask;
+
+    fn bitand(mut self, other: KeywordMask) -> KeywordMask {
+        self &= other;
+        self
+    }
+}
+
+impl BitAndAssign for KeywordMask {
+    fn bitand_assign(&mut self, other: KeywordMask) {
+        for (word, other) in self.bits.iter_mut().zip(&other.bits) {
+            *word &= other;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mask(bits: &[u8]) -> KeywordMask {
+        let mut mask = KeywordMask::new();
+        for &bit in bits {
+            mask.enable(bit);
+        }
+        mask
+    }
+
+    #[test]
+    fn toggles_bits_in_every_word() {
+        let mut keywords = mask(&[0, 63, 64, 255]);
+        assert!(keywords.contains(63) && keywords.contains(64) && keywords.contains(255));
+        assert!(!keywords.contains(1));
+        assert_eq!(keywords.len(), 4);
+        assert_eq!(keywords.iter().collect::<Vec<_>>(), [0, 63, 64, 255]);
+
+        keywords.disable(63).disable(2);
+        assert!(!keywords.contains(63));
+        assert_eq!(keywords.len(), 3);
+        keywords.disable(0).disable(64).disable(255);
+        assert!(keywords.is_empty());
+        assert_eq!(keywords, KeywordMask::new());
+    }
+
+    #[test]
+    fn combines_sets() {
+        let a = mask(&[1, 70, 200]);
+        let b = mask(&[70, 130]);
+        assert_eq!(a.union(&b), mask(&[1, 70, 130, 200]));
+        assert_eq!(a | b, a.union(&b));
+        assert_eq!(a.intersection(&b), mask(&[70]));
+        assert_eq!(a & b, a.intersection(&b));
+        assert_eq!(a.difference(&b), mask(&[1, 200]));
+        assert_eq!(b.difference(&a), mask(&[130]));
+
+        let mut c = a;
+        c |= b;
+        c &= mask(&[1, 130]);
+        assert_eq!(c, mask(&[1, 130]));
+    }
+}
@@ -0,0 +1,238 @@
+use crate::Stage;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+pub const SPIRV_MAGIC: u32 = 0x0723_0203;
+const HEADER_WORDS: usize = 5;
+const OP_ENTRY_POINT: u32 = 15;
+
+/// An owned SPIR-V binary with a checked header.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SpirvModule {
+    words: Vec<u32>,
+}
+
+impl SpirvModule {
+    pub fn new(words: Vec<u32>) -> Result<Self, SpirvError> {
+        if words.len() < HEADER_WORDS {
+            return Err(SpirvError::TooShort(words.len()));
+        }
+        if words[0] != SPIRV_MAGIC {
+            return Err(SpirvError::BadMagic(words[0]));
+        }
+
+        // Walk the instruction stream so a cut off module is caught here
+        let mut index = HEADER_WORDS;
+        while index < words.len() {
+            let count = (words[index] >> 16) as usize;
+            if count == 0 {
+                return Err(SpirvError::BadInstruction(index));
+            }
+            if index + count > words.len() {
+                return Err(SpirvError::Truncated(index));
+            }
+            index += count;
+        }
+        Ok(SpirvModule { words })
+    }
+
+    /// Decodes a binary of either endianness, big-endian modules are byte
+    /// swapped to native words.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, SpirvError> {
+        if !bytes.len().is_multiple_of(4) {
+            return Err(SpirvError::Misaligned(bytes.len()));
+        }
+
+        let mut words: Vec<u32> = bytes
+            .chunks_exact(4)
+            .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect();
+        if let Some(&magic) = words.first() {
+            if magic != SPIRV_MAGIC && magic.swap_bytes() == SPIRV_MAGIC {
+                for word in &mut words {
+                    *word = word.swap_bytes();
+                }
+            }
+        }
+        SpirvModule::new(words)
+    }
+
+    /// Little-endian bytes, as written by `write`
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.words.iter().flat_map(|w| w.to_le_bytes()).collect()
+    }
+
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Self, SpirvError> {
+        let bytes = fs::read(path).map_err(SpirvError::Io)?;
+        SpirvModule::from_bytes(&bytes)
+    }
+
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> Result<(), SpirvError> {
+        fs::write(path, self.to_bytes()).map_err(SpirvError::Io)
+    }
+
+    pub fn words(&self) -> &[u32] {
+        &self.words
+    }
+
+    pub fn into_words(self) -> Vec<u32> {
+        self.words
+    }
+
+    /// (major, minor) SPIR-V version
+    pub fn version(&self) -> (u8, u8) {
+        let version = self.words[1];
+        ((version >> 16) as u8, (version >> 8) as u8)
+    }
+
+    /// Generator magic, the tool ID in the high 16 bits and its version in
+    /// the low 16
+    pub fn generator(&self) -> u32 {
+        self.words[2]
+    }
+
+    /// Upper bound on the result IDs used in the module
+    pub fn bound(&self) -> u32 {
+        self.words[3]
+    }
+
+    /// Stage of the first OpEntryPoint, if the module has one we know
+    pub fn stage(&self) -> Option<Stage> {
+        let mut index = HEADER_WORDS;
+        while index < self.words.len() {
+            let instruction = self.words[index];
+            let count = (instruction >> 16) as usize;
+            if instruction & 0xffff == OP_ENTRY_POINT && index + 1 < self.words.len() {
+                return Stage::from_execution_model(self.words[index + 1]);
+            }
+            if count == 0 {
+                break;
+            }
+            index += count;
+        }
+        None
+    }
+}
+
+impl AsRef<[u32]> for SpirvModule {
+    fn as_ref(&self) -> &[u32] {
+        &self.words
+    }
+}
+
+#[derive(Debug)]
+pub enum SpirvError {
+    Io(io::Error),
+    /// Byte length that isn't a whole number of words
+    Misaligned(usize),
+    /// Fewer words than the five word header
+    TooShort(usize),
+    BadMagic(u32),
+    /// Word offset of an instruction that runs past the end of the module
+    Truncated(usize),
+    /// Word offset of an instruction with a zero word count
+    BadInstruction(usize),
+    /// The program isn't linked or has no such stage
+    MissingStage(Stage),
+}
+
+impl fmt::Display for SpirvError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SpirvError::Io(e) => write!(f, "cannot access SPIR-V file: {}", e),
+            SpirvError::Misaligned(bytes) => {
+                write!(f, "SPIR-V file is {} bytes, not a multiple of 4", bytes)
+            }
+            SpirvError::TooShort(words) => {
+                write!(
+                    f,
+                    "SPIR-V module is only {} words, too short for a header",
+                    words
+                )
+            }
+            SpirvError::BadMagic(magic) => {
+                write!(f, "bad SPIR-V magic number 0x{:08x}", magic)
+            }
+            SpirvError::Truncated(index) => {
+                write!(f, "SPIR-V module is truncated at word {}", index)
+            }
+            SpirvError::BadInstruction(index) => {
+                write!(f, "SPIR-V instruction at word {} has no length", index)
+            }
+            SpirvError::MissingStage(stage) => {
+                write!(f, "no linked SPIR-V for the {} stage", stage.extension())
+            }
+        }
+    }
+}
+
+impl std::error::Error for SpirvError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Header, then an OpEntryPoint for the fragment stage: execution model,
+    // function ID and the name "main"
+    fn fragment_words() -> Vec<u32> {
+        vec![
+            SPIRV_MAGIC,
+            0x0001_0300,
+            7,
+            20,
+            0,
+            (5 << 16) | OP_ENTRY_POINT,
+            4,
+            1,
+            u32::from_le_bytes(*b"main"),
+            0,
+        ]
+    }
+
+    #[test]
+    fn reads_either_endianness() {
+        let words = fragment_words();
+        let little: Vec<u8> = words.iter().flat_map(|w| w.to_le_bytes()).collect();
+        let big: Vec<u8> = words.iter().flat_map(|w| w.to_be_bytes()).collect();
+
+        let module = SpirvModule::from_bytes(&little).unwrap();
+        assert_eq!(module.words(), &words[..]);
+        assert_eq!(SpirvModule::from_bytes(&big).unwrap(), module);
+        assert_eq!(module.to_bytes(), little);
+        assert_eq!(module.version(), (1, 3));
+        assert_eq!(module.stage(), Some(Stage::FragmentStage));
+    }
+
+    #[test]
+    fn rejects_bad_binaries() {
+        let bytes = SpirvModule::new(fragment_words()).unwrap().to_bytes();
+        assert!(matches!(
+            SpirvModule::from_bytes(&bytes[..bytes.len() - 1]),
+            Err(SpirvError::Misaligned(39))
+        ));
+        assert!(matches!(
+            SpirvModule::new(vec![SPIRV_MAGIC, 0, 0, 0]),
+            Err(SpirvError::TooShort(4))
+        ));
+        assert!(matches!(
+            SpirvModule::new(vec![0x1234_5678, 0, 0, 0, 0]),
+            Err(SpirvError::BadMagic(0x1234_5678))
+        ));
+
+        let mut words = fragment_words();
+        words.pop();
+        assert!(matches!(
+            SpirvModule::new(words),
+            Err(SpirvError::Truncated(5))
+        ));
+
+        let mut words = fragment_words();
+        words.push(0);
+        assert!(matches!(
+            SpirvModule::new(words),
+            Err(SpirvError::BadInstruction(10))
+        ));
+    }
+}
@@ -0,0 +1,249 @@
+use std::fmt;
+
+/// Optimisation recipe for `ShaderCompiler::optimize`. The performance and
+/// size presets also strip debug info, as they're meant for shipping builds.
+#[derive(Clone, Debug, PartialEq)]
+pub enum OptLevel {
+    None,
+    Performance,
+    Size,
+    Custom(Vec<OptPass>),
+}
+
+impl OptLevel {
+    /// `none`, `performance`, `size`, or a comma separated list of spirv-opt
+    /// flags for a custom recipe
+    pub fn parse(text: &str) -> Option<Self> {
+        match text {
+            "none" => Some(OptLevel::None),
+            "performance" => Some(OptLevel::Performance),
+            "size" => Some(OptLevel::Size),
+            _ => {
+                let flags: Vec<&str> = text.split(',').map(|f| f.trim()).collect();
+                if flags.iter().any(|f| !f.starts_with("--") || f.len() == 2) {
+                    return None;
+                }
+                Some(OptLevel::Custom(
+                    flags.into_iter().map(OptPass::from_flag).collect(),
+                ))
+            }
+        }
+    }
+
+    // Matches the level values Optimize in spirv.cpp expects
+    pub(crate) fn preset(&self) -> i32 {
+        match self {
+            OptLevel::None => 0,
+            OptLevel::Performance => 1,
+            OptLevel::Size => 2,
+            OptLevel::Custom(_) => 3,
+        }
+    }
+
+    pub(crate) fn flags(&self) -> Vec<String> {
+        match self {
+            OptLevel::Custom(passes) => passes.iter().map(|p| p.flag()).collect(),
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// A single SPIRV-Tools pass, run in list order by `OptLevel::Custom`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum OptPass {
+    DeadBranchElimination,
+    AggressiveDeadCodeElimination,
+    InlineExhaustive,
+    MergeBlocks,
+    MergeReturn,
+    LocalSingleStoreElimination,
+    LocalMultiStoreElimination,
+    ScalarReplacement,
+    CopyPropagateArrays,
+    RedundancyElimination,
+    FoldSpecConstants,
+    FreezeSpecConstants,
+    CompactIds,
+    StripDebugInfo,
+    /// Any other pass, given as its spirv-opt command line flag
+    Flag(String),
+}
+
+impl OptPass {
+    const NAMED: [OptPass; 14] = [
+        OptPass::DeadBranchElimination,
+        OptPass::AggressiveDeadCodeElimination,
+        OptPass::InlineExhaustive,
+        OptPass::MergeBlocks,
+        OptPass::MergeReturn,
+        OptPass::LocalSingleStoreElimination,
+        OptPass::LocalMultiStoreElimination,
+        OptPass::ScalarReplacement,
+        OptPass::CopyPropagateArrays,
+        OptPass::RedundancyElimination,
+        OptPass::FoldSpecConstants,
+        OptPass::FreezeSpecConstants,
+        OptPass::CompactIds,
+        OptPass::StripDebugInfo,
+    ];
+
+    /// The named pass for a spirv-opt flag, `Flag` for any other
+    pub fn from_flag(flag: &str) -> Self {
+        OptPass::NAMED
+            .iter()
+            .find(|pass| pass.flag() == flag)
+            .cloned()
+            .unwrap_or_else(|| OptPass::Flag(flag.to_string()))
+    }
+
+    pub fn flag(&self) -> String {
+        let flag = match self {
+            OptPass::DeadBranchElimination => "--eliminate-dead-branches",
+            OptPass::AggressiveDeadCodeElimination => "--eliminate-dead-code-aggressive",
+            OptPass::InlineExhaustive => "--inline-entry-points-exhaustive",
+            OptPass::MergeBlocks => "--merge-blocks",
+            OptPass::MergeReturn => "--merge-return",
+            OptPass::LocalSingleStoreElimination => "--eliminate-local-single-store",
+            OptPass::LocalMultiStoreElimination => "--eliminate-local-multi-store",
+            OptPass::ScalarReplacement => "--scalar-replacement",
+            OptPass::CopyPropagateArrays => "--copy-propagate-arrays",
+            OptPass::RedundancyElimination => "--redundancy-elimination",
+            OptPass::FoldSpecConstants => "--fold-spec-const-op-composite",
+            OptPass::FreezeSpecConstants => "--freeze-spec-const",
+            OptPass::CompactIds => "--compact-ids",
+            OptPass::StripDebugInfo => "--strip-debug",
+            OptPass::Flag(flag) => flag,
+        };
+        flag.to_string()
+    }
+}
+
+/// SPIR-V size in words before and after an optimisation run.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct OptReport {
+    pub before: usize,
+    pub after: usize,
+}
+
+impl OptReport {
+    pub fn bytes_before(&self) -> usize {
+        self.before * std::mem::size_of::<u32>()
+    }
+
+    pub fn bytes_after(&self) -> usize {
+        self.after * std::mem::size_of::<u32>()
+    }
+}
+
+impl fmt::Display for OptReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let saved = if self.before == 0 {
+            0.0
+        } else {
+            100.0 - (self.after as f64 * 100.0 / self.before as f64)
+        };
+        write!(
+            f,
+            "{} -> {} bytes ({:.1}% smaller)",
+            self.bytes_before(),
+            self.bytes_after(),
+            saved
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::limits::ResourceLimits;
+    use crate::{ShaderCompiler, Stage};
+
+    const SHADER: &str = "#version 450
+layout(location = 0) out vec4 colour;
+vec4 tint(vec4 c) { return c * 0.5; }
+void main() {
+    vec4 unused = vec4(2.0);
+    colour = tint(vec4(1.0));
+}
+";
+
+    // OpName and OpMemberName, which stripping debug info removes
+    fn has_names(words: &[u32]) -> bool {
+        let mut index = 5;
+        while index < words.len() {
+            let opcode = words[index] & 0xffff;
+            if opcode == 5 || opcode == 6 {
+                return true;
+            }
+            index += (words[index] >> 16).max(1) as usize;
+        }
+        false
+    }
+
+    #[test]
+    fn presets_match_optimize_levels() {
+        assert_eq!(OptLevel::None.preset(), 0);
+        assert_eq!(OptLevel::Performance.preset(), 1);
+        assert_eq!(OptLevel::Size.preset(), 2);
+        assert_eq!(OptLevel::Custom(Vec::new()).preset(), 3);
+        assert!(OptLevel::Size.flags().is_empty());
+    }
+
+    #[test]
+    fn custom_recipes_keep_their_order() {
+        let level = OptLevel::Custom(vec![
+            OptPass::MergeReturn,
+            OptPass::Flag("--unify-const".to_string()),
+            OptPass::StripDebugInfo,
+        ]);
+        assert_eq!(
+            level.flags(),
+            ["--merge-return", "--unify-const", "--strip-debug"]
+        );
+    }
+
+    #[test]
+    fn parses_levels_and_flag_lists() {
+        assert_eq!(OptLevel::parse("size"), Some(OptLevel::Size));
+        assert_eq!(
+            OptLevel::parse("--merge-blocks, --unify-const"),
+            Some(OptLevel::Custom(vec![
+                OptPass::MergeBlocks,
+                OptPass::Flag("--unify-const".to_string()),
+            ]))
+        );
+        assert_eq!(OptLevel::parse("fast"), None);
+        assert_eq!(OptLevel::parse("--merge-blocks,"), None);
+        for pass in &OptPass::NAMED {
+            assert_eq!(&OptPass::from_flag(&pass.flag()), pass);
+        }
+    }
+
+    #[test]
+    fn recipes_shrink_valid_modules() {
+        let mut compiler = ShaderCompiler::new(&ResourceLimits::default());
+        let module = compiler.compile_stage(Stage::FragmentStage, SHADER);
+        assert!(has_names(module.words()));
+
+        for level in &[OptLevel::Performance, OptLevel::Size] {
+            let mut optimized = module.clone();
+            let report = compiler.optimize(&mut optimized, level).unwrap();
+            assert_eq!(report.before, module.words().len());
+            assert!(report.after < report.before, "{:?}", level);
+            assert!(compiler.validate(&optimized).is_ok());
+            assert!(!has_names(optimized.words()));
+        }
+
+        let mut stripped = module.clone();
+        let level = OptLevel::Custom(vec![OptPass::StripDebugInfo]);
+        compiler.optimize(&mut stripped, &level).unwrap();
+        assert!(!has_names(stripped.words()));
+        assert!(compiler.validate(&stripped).is_ok());
+
+        // An unknown pass fails the run and leaves the module alone
+        let mut unchanged = module.clone();
+        let level = OptLevel::Custom(vec![OptPass::Flag("--no-such-pass".to_string())]);
+        assert_eq!(compiler.optimize(&mut unchanged, &level), None);
+        assert_eq!(unchanged, module);
+    }
+}
@@ -0,0 +1,127 @@
+// Bit values mirror the TOptions enum in spirv.cpp
+const OPTION_RELAXED_ERRORS: u64 = 1 << 3;
+const OPTION_DEFAULT_DESKTOP: u64 = 1 << 14;
+const OPTION_AUTO_MAP_BINDINGS: u64 = 1 << 19;
+const OPTION_HLSL_OFFSETS: u64 = 1 << 23;
+const OPTION_HLSL_IO_MAPPING: u64 = 1 << 24;
+const OPTION_AUTO_MAP_LOCATIONS: u64 = 1 << 25;
+const OPTION_DEBUG: u64 = 1 << 26;
+const OPTION_INVERT_Y: u64 = 1 << 30;
+// Not part of TOptions, lives above the 32 bits glslang's own flags use
+const OPTION_NAN_MIN_MAX_CLAMP: u64 = 1 << 32;
+
+/// glslang behaviour flags for a single compile, passed to both
+/// `ShaderCompiler::compile` and `ShaderCompiler::get_spirv_for_stage`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct CompileOptions {
+    pub invert_y: bool,
+    pub auto_map_bindings: bool,
+    pub auto_map_locations: bool,
+    pub nan_min_max_clamp: bool,
+    pub debug_info: bool,
+    pub relaxed_errors: bool,
+    pub hlsl_offsets: bool,
+    pub hlsl_io_mapping: bool,
+    pub default_desktop: bool,
+}
+
+impl Default for CompileOptions {
+    fn default() -> Self {
+        CompileOptions {
+            invert_y: true,
+            auto_map_bindings: true,
+            auto_map_locations: true,
+            nan_min_max_clamp: false,
+            debug_info: true,
+            relaxed_errors: false,
+            hlsl_offsets: false,
+            hlsl_io_mapping: false,
+            default_desktop: false,
+        }
+    }
+}
+
+impl CompileOptions {
+    /// Options for OpenGL-backed tooling, which must never see Y inverted.
+    pub fn opengl() -> Self {
+        CompileOptions {
+            invert_y: false,
+            ..Default::default()
+        }
+    }
+
+    pub fn bits(&self) -> u64 {
+        let flags = [
+            (self.invert_y, OPTION_INVERT_Y),
+            (self.auto_map_bindings, OPTION_AUTO_MAP_BINDINGS),
+            (self.auto_map_locations, OPTION_AUTO_MAP_LOCATIONS),
+            (self.nan_min_max_clamp, OPTION_NAN_MIN_MAX_CLAMP),
+            (self.debug_info, OPTION_DEBUG),
+            (self.relaxed_errors, OPTION_RELAXED_ERRORS),
+            (self.hlsl_offsets, OPTION_HLSL_OFFSETS),
+            (self.hlsl_io_mapping, OPTION_HLSL_IO_MAPPING),
+            (self.default_desktop, OPTION_DEFAULT_DESKTOP),
+        ];
+
+        let mut bits = 0;
+        for (enabled, bit) in &flags {
+            if *enabled {
+                bits |= bit;
+            }
+        }
+        bits
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn none() -> CompileOptions {
+        CompileOptions {
+            invert_y: false,
+            auto_map_bindings: false,
+            auto_map_locations: false,
+            nan_min_max_clamp: false,
+            debug_info: false,
+            relaxed_errors: false,
+            hlsl_offsets: false,
+            hlsl_io_mapping: false,
+            default_desktop: false,
+        }
+    }
+
+    fn only<F: Fn(&mut CompileOptions)>(set: F) -> u64 {
+        let mut options = none();
+        set(&mut options);
+        options.bits()
+    }
+
+    #[test]
+    fn each_option_sets_its_toptions_bit() {
+        // The TOptions values from spirv.cpp, spelled out so a renumbering on
+        // either side shows up here
+        assert_eq!(none().bits(), 0);
+        assert_eq!(only(|o| o.relaxed_errors = true), 1 << 3);
+        assert_eq!(only(|o| o.default_desktop = true), 1 << 14);
+        assert_eq!(only(|o| o.auto_map_bindings = true), 1 << 19);
+        assert_eq!(only(|o| o.hlsl_offsets = true), 1 << 23);
+        assert_eq!(only(|o| o.hlsl_io_mapping = true), 1 << 24);
+        assert_eq!(only(|o| o.auto_map_locations = true), 1 << 25);
+        assert_eq!(only(|o| o.debug_info = true), 1 << 26);
+        assert_eq!(only(|o| o.invert_y = true), 1 << 30);
+        assert_eq!(only(|o| o.nan_min_max_clamp = true), 1 << 32);
+    }
+
+    #[test]
+    fn default_and_opengl_presets() {
+        assert_eq!(
+            CompileOptions::default().bits(),
+            (1 << 30) | (1 << 19) | (1 << 25) | (1 << 26)
+        );
+        assert_eq!(
+            CompileOptions::opengl().bits(),
+            CompileOptions::default().bits() & !(1 << 30)
+        );
+    }
+}
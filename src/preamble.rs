@@ -0,0 +1,143 @@
+use crate::ShaderCompiler;
+use std::ops::{Deref, DerefMut};
+
+/// Preambles are layered Global, then Shader, then Variant, and in push order
+/// within a scope, so a variant can test the platform defines above it.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum PreambleScope {
+    Global,
+    Shader,
+    Variant,
+}
+
+struct Entry {
+    id: u64,
+    scope: PreambleScope,
+    source: String,
+}
+
+#[derive(Default)]
+pub(crate) struct PreambleStack {
+    entries: Vec<Entry>,
+    next_id: u64,
+}
+
+impl PreambleStack {
+    pub(crate) fn push(&mut self, scope: PreambleScope, source: &str) -> u64 {
+        self.next_id += 1;
+        self.entries.push(Entry {
+            id: self.next_id,
+            scope,
+            source: source.to_string(),
+        });
+        self.next_id
+    }
+
+    pub(crate) fn remove(&mut self, id: u64) -> Option<String> {
+        let index = self.entries.iter().position(|e| e.id == id)?;
+        Some(self.entries.remove(index).source)
+    }
+
+    pub(crate) fn clear(&mut self, scope: PreambleScope) {
+        self.entries.retain(|e| e.scope != scope);
+    }
+
+    /// The preamble glslang sees, each entry on its own lines
+    pub(crate) fn compose(&self) -> String {
+        let mut entries: Vec<&Entry> = self.entries.iter().collect();
+        entries.sort_by_key(|e| (e.scope, e.id));
+
+        let mut preamble = String::new();
+        for entry in entries {
+            preamble.push_str(&entry.source);
+            if !entry.source.ends_with('\n') {
+                preamble.push('\n');
+            }
+        }
+        preamble
+    }
+}
+
+/// Keeps a preamble on the compiler's stack until dropped. Derefs to the
+/// compiler so a variant guard can be pushed through a shader guard.
+pub(crate) struct PreambleGuard<'a> {
+    compiler: &'a mut ShaderCompiler,
+    id: u64,
+}
+
+impl<'a> PreambleGuard<'a> {
+    pub(crate) fn new(compiler: &'a mut ShaderCompiler, id: u64) -> Self {
+        PreambleGuard { compiler, id }
+    }
+
+    /// Pops the preamble now, returning its source
+    pub fn pop_preamble(self) -> String {
+        let mut guard = std::mem::ManuallyDrop::new(self);
+        let id = guard.id;
+        guard.compiler.preambles.remove(id).unwrap_or_default()
+    }
+}
+
+impl Deref for PreambleGuard<'_> {
+    type Target = ShaderCompiler;
+
+    fn deref(&self) -> &ShaderCompiler {
+        self.compiler
+    }
+}
+
+impl DerefMut for PreambleGuard<'_> {
+    fn deref_mut(&mut self) -> &mut ShaderCompiler {
+        self.compiler
+    }
+}
+
+impl Drop for PreambleGuard<'_> {
+    fn drop(&mut self) {
+        self.compiler.preambles.remove(self.id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::limits::ResourceLimits;
+
+    #[test]
+    fn scopes_compose_global_shader_variant() {
+        let mut stack = PreambleStack::default();
+        stack.push(PreambleScope::Variant, "#define VARIANT");
+        stack.push(PreambleScope::Global, "#define PLATFORM 1\n");
+        let shader = stack.push(PreambleScope::Shader, "#define SHADER");
+        stack.push(PreambleScope::Global, "#define VENDOR 2");
+        assert_eq!(
+            stack.compose(),
+            "#define PLATFORM 1\n#define VENDOR 2\n#define SHADER\n#define VARIANT\n"
+        );
+
+        assert_eq!(stack.remove(shader).as_deref(), Some("#define SHADER"));
+        assert_eq!(stack.remove(shader), None);
+        stack.clear(PreambleScope::Global);
+        assert_eq!(stack.compose(), "#define VARIANT\n");
+    }
+
+    #[test]
+    fn guards_pop_only_their_own_preamble() {
+        let mut compiler = ShaderCompiler::new(&ResourceLimits::default());
+        compiler.set_preamble("#define PLATFORM 1");
+        {
+            let mut shader = compiler.push_preamble(PreambleScope::Shader, "#define SHADER");
+            let variant = shader.push_preamble(PreambleScope::Variant, "#define VARIANT");
+            assert_eq!(
+                variant.preamble(),
+                "#define PLATFORM 1\n#define SHADER\n#define VARIANT\n"
+            );
+            assert_eq!(variant.pop_preamble(), "#define VARIANT");
+            assert_eq!(shader.preamble(), "#define PLATFORM 1\n#define SHADER\n");
+        }
+        // The global preamble outlives the guards
+        assert_eq!(compiler.preamble(), "#define PLATFORM 1\n");
+        compiler.clear_preamble();
+        assert_eq!(compiler.preamble(), "");
+    }
+}
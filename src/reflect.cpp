@@ -0,0 +1,213 @@
+#include "reflect.h"
+
+#include <cstdio>
+
+thread_local std::deque<std::string> reflectionNames;
+thread_local std::vector<RawResource> reflectionResources;
+thread_local std::vector<RawMember> reflectionMembers;
+thread_local std::vector<RawVariable> reflectionVariables;
+thread_local std::vector<RawEntryPoint> reflectionEntryPoints;
+thread_local RawReflection reflection;
+
+// The deque keeps the strings at a stable address while the C views point at
+// them
+static const char* KeepName(const std::string& name) {
+  reflectionNames.push_back(name);
+  return reflectionNames.back().c_str();
+}
+
+int ReflectBaseType(const spirv_cross::SPIRType& type) {
+  switch (type.basetype) {
+    case spirv_cross::SPIRType::Boolean:
+      return EBaseBool;
+    case spirv_cross::SPIRType::SByte:
+      return EBaseInt8;
+    case spirv_cross::SPIRType::UByte:
+      return EBaseUInt8;
+    case spirv_cross::SPIRType::Short:
+      return EBaseInt16;
+    case spirv_cross::SPIRType::UShort:
+      return EBaseUInt16;
+    case spirv_cross::SPIRType::Int:
+      return EBaseInt;
+    case spirv_cross::SPIRType::UInt:
+      return EBaseUInt;
+    case spirv_cross::SPIRType::Int64:
+      return EBaseInt64;
+    case spirv_cross::SPIRType::UInt64:
+      return EBaseUInt64;
+    case spirv_cross::SPIRType::Half:
+      return EBaseHalf;
+    case spirv_cross::SPIRType::Float:
+      return EBaseFloat;
+    case spirv_cross::SPIRType::Double:
+      return EBaseDouble;
+    case spirv_cross::SPIRType::Struct:
+      return EBaseStruct;
+    case spirv_cross::SPIRType::Image:
+      return EBaseImage;
+    case spirv_cross::SPIRType::SampledImage:
+      return EBaseSampledImage;
+    case spirv_cross::SPIRType::Sampler:
+      return EBaseSampler;
+    default:
+      return EBaseUnknown;
+  }
+}
+
+// Length of the outermost dimension, which SPIRV-Cross keeps last, so
+// float m[2][3] reports 2. Runtime sized arrays report 0, and arrays sized by
+// a specialization constant report its default value. A size SPIRV-Cross
+// can't evaluate reports 0 too rather than failing the whole module.
+static uint32_t ArraySize(spirv_cross::Compiler& compiler,
+                          const spirv_cross::SPIRType& type) {
+  if (type.array.empty())
+    return 1;
+  uint32_t size = type.array.back();
+  if (type.array_size_literal.back())
+    return size;
+  try {
+    return compiler.evaluate_constant_u32(size);
+  } catch (const spirv_cross::CompilerError&) {
+    return 0;
+  }
+}
+
+// Flattens the members of a block, nested structs get dotted names and
+// offsets relative to the start of the block. Arrays of structs are walked
+// through their first element, lights[0].colour, the array stride locates
+// the rest
+static void AddMembers(spirv_cross::Compiler& compiler,
+                       const spirv_cross::SPIRType& type,
+                       const std::string& prefix,
+                       uint32_t base) {
+  for (uint32_t i = 0; i < type.member_types.size(); ++i) {
+    const spirv_cross::SPIRType& member =
+        compiler.get_type(type.member_types[i]);
+    std::string name = prefix + compiler.get_member_name(type.self, i);
+    uint32_t offset = base + compiler.type_struct_member_offset(type, i);
+
+    RawMember raw{};
+    raw.name = KeepName(name);
+    raw.offset = offset;
+    raw.size = (uint32_t)compiler.get_declared_struct_member_size(type, i);
+    raw.baseType = ReflectBaseType(member);
+    raw.vecsize = member.vecsize;
+    raw.columns = member.columns;
+    raw.arraySize = ArraySize(compiler, member);
+    if (!member.array.empty())
+      raw.arrayStride = compiler.type_struct_member_array_stride(type, i);
+    if (member.columns > 1)
+      raw.matrixStride = compiler.type_struct_member_matrix_stride(type, i);
+    raw.rowMajor =
+        compiler.has_member_decoration(type.self, i, spv::DecorationRowMajor);
+    reflectionMembers.push_back(raw);
+
+    if (member.basetype == spirv_cross::SPIRType::Struct) {
+      std::string element = name;
+      for (size_t dimension = 0; dimension < member.array.size(); ++dimension)
+        element += "[0]";
+      AddMembers(compiler, member, element + ".", offset);
+    }
+  }
+}
+
+static void AddResources(
+    spirv_cross::Compiler& compiler,
+    const spirv_cross::SmallVector<spirv_cross::Resource>& resources,
+    ResourceKind kind) {
+  for (auto& resource : resources) {
+    const spirv_cross::SPIRType& type = compiler.get_type(resource.type_id);
+    RawResource raw{};
+    raw.kind = kind;
+    raw.name = KeepName(resource.name);
+    raw.set = compiler.get_decoration(resource.id,
+                                      spv::DecorationDescriptorSet);
+    raw.binding = compiler.get_decoration(resource.id, spv::DecorationBinding);
+    raw.arraySize = ArraySize(compiler, type);
+    raw.inputAttachmentIndex = compiler.get_decoration(
+        resource.id, spv::DecorationInputAttachmentIndex);
+    raw.firstMember = reflectionMembers.size();
+
+    const spirv_cross::SPIRType& base =
+        compiler.get_type(resource.base_type_id);
+    if (base.basetype == spirv_cross::SPIRType::Struct) {
+      raw.size = (uint32_t)compiler.get_declared_struct_size(base);
+      AddMembers(compiler, base, "", 0);
+    }
+    raw.memberCount = reflectionMembers.size() - raw.firstMember;
+    reflectionResources.push_back(raw);
+  }
+}
+
+static void AddVariables(
+    spirv_cross::Compiler& compiler,
+    const spirv_cross::SmallVector<spirv_cross::Resource>& variables,
+    bool output) {
+  for (auto& variable : variables) {
+    const spirv_cross::SPIRType& type = compiler.get_type(variable.type_id);
+    RawVariable raw{};
+    raw.output = output;
+    raw.name = KeepName(variable.name);
+    raw.location =
+        compiler.get_decoration(variable.id, spv::DecorationLocation);
+    raw.baseType = ReflectBaseType(type);
+    raw.vecsize = type.vecsize;
+    raw.columns = type.columns;
+    raw.arraySize = ArraySize(compiler, type);
+    reflectionVariables.push_back(raw);
+  }
+}
+
+extern "C" const RawReflection* Reflect(unsigned int* ptr, std::size_t length) {
+  reflectionNames.clear();
+  reflectionResources.clear();
+  reflectionMembers.clear();
+  reflectionVariables.clear();
+  reflectionEntryPoints.clear();
+  reflection = RawReflection{};
+
+  try {
+    spirv_cross::Compiler compiler(ptr, length);
+    spirv_cross::ShaderResources resources = compiler.get_shader_resources();
+
+    AddResources(compiler, resources.uniform_buffers, EResourceUniformBuffer);
+    AddResources(compiler, resources.storage_buffers, EResourceStorageBuffer);
+    AddResources(compiler, resources.sampled_images, EResourceSampledImage);
+    AddResources(compiler, resources.separate_images, EResourceSeparateImage);
+    AddResources(compiler, resources.separate_samplers,
+                 EResourceSeparateSampler);
+    AddResources(compiler, resources.storage_images, EResourceStorageImage);
+    AddResources(compiler, resources.subpass_inputs, EResourceSubpassInput);
+    AddResources(compiler, resources.push_constant_buffers,
+                 EResourcePushConstant);
+
+    AddVariables(compiler, resources.stage_inputs, false);
+    AddVariables(compiler, resources.stage_outputs, true);
+
+    for (auto& entry : compiler.get_entry_points_and_stages()) {
+      reflectionEntryPoints.push_back(
+          RawEntryPoint{KeepName(entry.name), (int)entry.execution_model});
+      if (entry.execution_model == spv::ExecutionModelGLCompute) {
+        compiler.set_entry_point(entry.name, entry.execution_model);
+        for (uint32_t i = 0; i < 3; ++i) {
+          reflection.workgroupSize[i] = compiler.get_execution_mode_argument(
+              spv::ExecutionModeLocalSize, i);
+        }
+      }
+    }
+  } catch (const spirv_cross::CompilerError& e) {
+    printf("Reflection Failed!\n%s\n", e.what());
+    return nullptr;
+  }
+
+  reflection.resources = reflectionResources.data();
+  reflection.resourceCount = reflectionResources.size();
+  reflection.members = reflectionMembers.data();
+  reflection.memberCount = reflectionMembers.size();
+  reflection.variables = reflectionVariables.data();
+  reflection.variableCount = reflectionVariables.size();
+  reflection.entryPoints = reflectionEntryPoints.data();
+  reflection.entryPointCount = reflectionEntryPoints.size();
+  return &reflection;
+}
@@ -0,0 +1,98 @@
+#pragma once
+#include <cstdint>
+#include <deque>
+#include <string>
+#include <vector>
+
+#include "spirv.h"
+
+// Flat C views of the SPIRV-Cross reflection data, read by src/reflect.rs
+
+enum ResourceKind {
+  EResourceUniformBuffer = 0,
+  EResourceStorageBuffer = 1,
+  EResourceSampledImage = 2,
+  EResourceSeparateImage = 3,
+  EResourceSeparateSampler = 4,
+  EResourceStorageImage = 5,
+  EResourceSubpassInput = 6,
+  EResourcePushConstant = 7,
+};
+
+enum ReflectBaseType {
+  EBaseUnknown = 0,
+  EBaseBool = 1,
+  EBaseInt8 = 2,
+  EBaseUInt8 = 3,
+  EBaseInt16 = 4,
+  EBaseUInt16 = 5,
+  EBaseInt = 6,
+  EBaseUInt = 7,
+  EBaseInt64 = 8,
+  EBaseUInt64 = 9,
+  EBaseHalf = 10,
+  EBaseFloat = 11,
+  EBaseDouble = 12,
+  EBaseStruct = 13,
+  EBaseImage = 14,
+  EBaseSampledImage = 15,
+  EBaseSampler = 16,
+};
+
+struct RawMember {
+  const char* name;
+  uint32_t offset;
+  uint32_t size;
+  int baseType;
+  uint32_t vecsize;
+  uint32_t columns;
+  uint32_t arraySize;
+  uint32_t arrayStride;
+  uint32_t matrixStride;
+  bool rowMajor;
+};
+
+struct RawResource {
+  int kind;
+  const char* name;
+  uint32_t set;
+  uint32_t binding;
+  uint32_t arraySize;
+  uint32_t inputAttachmentIndex;
+  uint32_t size;
+  std::size_t firstMember;
+  std::size_t memberCount;
+};
+
+struct RawVariable {
+  bool output;
+  const char* name;
+  uint32_t location;
+  int baseType;
+  uint32_t vecsize;
+  uint32_t columns;
+  uint32_t arraySize;
+};
+
+struct RawEntryPoint {
+  const char* name;
+  int model;
+};
+
+struct RawReflection {
+  const RawResource* resources;
+  std::size_t resourceCount;
+  const RawMember* members;
+  std::size_t memberCount;
+  const RawVariable* variables;
+  std::size_t variableCount;
+  const RawEntryPoint* entryPoints;
+  std::size_t entryPointCount;
+  uint32_t workgroupSize[3];
+};
+
+int ReflectBaseType(const spirv_cross::SPIRType& type);
+
+extern "C" {
+const RawReflection* Reflect(unsigned int* ptr, std::size_t length);
+}
@@ -0,0 +1,370 @@
+use crate::Stage;
+use serde::Serialize;
+use std::ffi::CStr;
+use std::os::raw::c_char;
+
+// Layouts of the Raw* structs in reflect.h
+
+#[repr(C)]
+struct RawMember {
+    name: *const c_char,
+    offset: u32,
+    size: u32,
+    base_type: i32,
+    vecsize: u32,
+    columns: u32,
+    array_size: u32,
+    array_stride: u32,
+    matrix_stride: u32,
+    row_major: bool,
+}
+
+#[repr(C)]
+struct RawResource {
+    kind: i32,
+    name: *const c_char,
+    set: u32,
+    binding: u32,
+    array_size: u32,
+    input_attachment_index: u32,
+    size: u32,
+    first_member: usize,
+    member_count: usize,
+}
+
+#[repr(C)]
+struct RawVariable {
+    output: bool,
+    name: *const c_char,
+    location: u32,
+    base_type: i32,
+    vecsize: u32,
+    columns: u32,
+    array_size: u32,
+}
+
+#[repr(C)]
+struct RawEntryPoint {
+    name: *const c_char,
+    model: i32,
+}
+
+#[repr(C)]
+pub(crate) struct RawReflection {
+    resources: *const RawResource,
+    resource_count: usize,
+    members: *const RawMember,
+    member_count: usize,
+    variables: *const RawVariable,
+    variable_count: usize,
+    entry_points: *const RawEntryPoint,
+    entry_point_count: usize,
+    workgroup_size: [u32; 3],
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize)]
+pub enum ResourceKind {
+    UniformBuffer,
+    StorageBuffer,
+    SampledImage,
+    SeparateImage,
+    SeparateSampler,
+    StorageImage,
+    SubpassInput,
+    PushConstant,
+}
+
+impl ResourceKind {
+    pub(crate) fn from_raw(kind: i32) -> Self {
+        match kind {
+            0 => ResourceKind::UniformBuffer,
+            1 => ResourceKind::StorageBuffer,
+            2 => ResourceKind::SampledImage,
+            3 => ResourceKind::SeparateImage,
+            4 => ResourceKind::SeparateSampler,
+            5 => ResourceKind::StorageImage,
+            6 => ResourceKind::SubpassInput,
+            _ => ResourceKind::PushConstant,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum BaseType {
+    Unknown,
+    Bool,
+    Int8,
+    UInt8,
+    Int16,
+    UInt16,
+    Int,
+    UInt,
+    Int64,
+    UInt64,
+    Half,
+    Float,
+    Double,
+    Struct,
+    Image,
+    SampledImage,
+    Sampler,
+}
+
+impl BaseType {
+    fn from_raw(base_type: i32) -> Self {
+        match base_type {
+            1 => BaseType::Bool,
+            2 => BaseType::Int8,
+            3 => BaseType::UInt8,
+            4 => BaseType::Int16,
+            5 => BaseType::UInt16,
+            6 => BaseType::Int,
+            7 => BaseType::UInt,
+            8 => BaseType::Int64,
+            9 => BaseType::UInt64,
+            10 => BaseType::Half,
+            11 => BaseType::Float,
+            12 => BaseType::Double,
+            13 => BaseType::Struct,
+            14 => BaseType::Image,
+            15 => BaseType::SampledImage,
+            16 => BaseType::Sampler,
+            _ => BaseType::Unknown,
+        }
+    }
+}
+
+/// A member of a uniform, storage or push constant block. Members of nested
+/// structs are flattened with dotted names and block-relative offsets, those
+/// of arrays of structs once, for the first element, as `lights[0].colour`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BlockMember {
+    pub name: String,
+    pub offset: u32,
+    pub size: u32,
+    pub base_type: BaseType,
+    pub vecsize: u32,
+    pub columns: u32,
+    /// Length of the outermost dimension, 1 for non-arrays and 0 for
+    /// runtime sized arrays or sizes SPIRV-Cross can't evaluate
+    pub array_size: u32,
+    /// Stride of the outermost dimension
+    pub array_stride: u32,
+    pub matrix_stride: u32,
+    pub row_major: bool,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Resource {
+    pub kind: ResourceKind,
+    pub name: String,
+    pub set: u32,
+    pub binding: u32,
+    pub array_size: u32,
+    pub input_attachment_index: u32,
+    /// Declared size of the block, 0 for non-block resources
+    pub size: u32,
+    pub members: Vec<BlockMember>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct StageVariable {
+    pub name: String,
+    pub location: u32,
+    pub base_type: BaseType,
+    pub vecsize: u32,
+    pub columns: u32,
+    pub array_size: u32,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct EntryPoint {
+    pub name: String,
+    /// None for execution models we have no Stage for
+    pub stage: Option<Stage>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ShaderReflection {
+    pub resources: Vec<Resource>,
+    pub inputs: Vec<StageVariable>,
+    pub outputs: Vec<StageVariable>,
+    pub entry_points: Vec<EntryPoint>,
+    /// Local workgroup size of a compute entry point
+    pub workgroup_size: Option<[u32; 3]>,
+}
+
+impl ShaderReflection {
+    pub fn resources_of(&self, kind: ResourceKind) -> impl Iterator<Item = &Resource> {
+        self.resources.iter().filter(move |r| r.kind == kind)
+    }
+
+    pub fn uniform_buffers(&self) -> impl Iterator<Item = &Resource> {
+        self.resources_of(ResourceKind::UniformBuffer)
+    }
+
+    pub fn storage_buffers(&self) -> impl Iterator<Item = &Resource> {
+        self.resources_of(ResourceKind::StorageBuffer)
+    }
+
+    pub fn push_constants(&self) -> impl Iterator<Item = &Resource> {
+        self.resources_of(ResourceKind::PushConstant)
+    }
+}
+
+pub(crate) unsafe fn name(ptr: *const c_char) -> String {
+    CStr::from_ptr(ptr).to_string_lossy().into_owned()
+}
+
+pub(crate) unsafe fn slice<'a, T>(ptr: *const T, count: usize) -> &'a [T] {
+    if count == 0 {
+        &[]
+    } else {
+        std::slice::from_raw_parts(ptr, count)
+    }
+}
+
+pub(crate) unsafe fn collect(raw: &RawReflection) -> ShaderReflection {
+    let members = slice(raw.members, raw.member_count);
+    let mut reflection = ShaderReflection::default();
+
+    for r in slice(raw.resources, raw.resource_count) {
+        let block = &members[r.first_member..r.first_member + r.member_count];
+        reflection.resources.push(Resource {
+            kind: ResourceKind::from_raw(r.kind),
+            name: name(r.name),
+            set: r.set,
+            binding: r.binding,
+            array_size: r.array_size,
+            input_attachment_index: r.input_attachment_index,
+            size: r.size,
+            members: block
+                .iter()
+                .map(|m| BlockMember {
+                    name: name(m.name),
+                    offset: m.offset,
+                    size: m.size,
+                    base_type: BaseType::from_raw(m.base_type),
+                    vecsize: m.vecsize,
+                    columns: m.columns,
+                    array_size: m.array_size,
+                    array_stride: m.array_stride,
+                    matrix_stride: m.matrix_stride,
+                    row_major: m.row_major,
+                })
+                .collect(),
+        });
+    }
+
+    for v in slice(raw.variables, raw.variable_count) {
+        let variable = StageVariable {
+            name: name(v.name),
+            location: v.location,
+            base_type: BaseType::from_raw(v.base_type),
+            vecsize: v.vecsize,
+            columns: v.columns,
+            array_size: v.array_size,
+        };
+        if v.output {
+            reflection.outputs.push(variable);
+        } else {
+            reflection.inputs.push(variable);
+        }
+    }
+
+    for e in slice(raw.entry_points, raw.entry_point_count) {
+        let stage = Stage::from_execution_model(e.model as u32);
+        if stage == Some(Stage::ComputeStage) {
+            reflection.workgroup_size = Some(raw.workgroup_size);
+        }
+        reflection.entry_points.push(EntryPoint {
+            name: name(e.name),
+            stage,
+        });
+    }
+    reflection
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::limits::ResourceLimits;
+    use crate::ShaderCompiler;
+
+    const SHADER: &str = "#version 450
+layout(constant_id = 0) const int COUNT = 3;
+struct Light {
+    vec4 colour;
+    vec3 direction;
+    float range;
+};
+layout(set = 0, binding = 0) uniform Frame {
+    mat4 view;
+    float grid[2][3];
+    vec4 fixed[COUNT];
+    vec4 doubled[COUNT * 2];
+    Light lights[2];
+} frame;
+layout(set = 0, binding = 1) buffer Particles {
+    vec4 positions[];
+} particles;
+layout(location = 0) out vec4 colour;
+void main() {
+    colour = frame.view[0] + frame.fixed[0] + frame.doubled[0] + frame.lights[1].colour
+        + particles.positions[0] + vec4(frame.grid[1][2]);
+}
+";
+
+    fn reflect() -> ShaderReflection {
+        let mut compiler = ShaderCompiler::new(&ResourceLimits::default());
+        let module = compiler.compile_stage(Stage::FragmentStage, SHADER);
+        compiler.reflect(&module).unwrap()
+    }
+
+    fn member<'a>(block: &'a Resource, name: &str) -> &'a BlockMember {
+        block.members.iter().find(|m| m.name == name).unwrap()
+    }
+
+    #[test]
+    fn arrays_report_their_outermost_dimension() {
+        let reflection = reflect();
+        let frame = reflection.uniform_buffers().next().unwrap();
+        let grid = member(frame, "grid");
+        assert_eq!(
+            (grid.offset, grid.array_size, grid.array_stride),
+            (64, 2, 48)
+        );
+        assert_eq!(member(frame, "fixed").array_size, 3);
+    }
+
+    #[test]
+    fn spec_constant_expressions_size_arrays() {
+        // COUNT * 2 is an OpSpecConstantOp, which used to fail the whole module
+        let reflection = reflect();
+        let frame = reflection.uniform_buffers().next().unwrap();
+        assert_eq!(member(frame, "doubled").array_size, 6);
+        assert_eq!(reflection.outputs[0].name, "colour");
+    }
+
+    #[test]
+    fn arrays_of_structs_are_walked_through_their_first_element() {
+        let reflection = reflect();
+        let frame = reflection.uniform_buffers().next().unwrap();
+        let lights = member(frame, "lights");
+        assert_eq!(
+            (lights.offset, lights.array_size, lights.array_stride),
+            (304, 2, 32)
+        );
+        assert_eq!(member(frame, "lights[0].colour").offset, 304);
+        assert_eq!(member(frame, "lights[0].direction").offset, 320);
+        assert_eq!(member(frame, "lights[0].range").offset, 332);
+    }
+
+    #[test]
+    fn runtime_arrays_report_zero() {
+        let reflection = reflect();
+        let particles = reflection.storage_buffers().next().unwrap();
+        assert_eq!(particles.name, "Particles");
+        assert_eq!(member(particles, "positions").array_size, 0);
+    }
+}
@@ -0,0 +1,271 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::os::raw::c_char;
+use std::path::Path;
+
+/// FNV-1a 64, the same as `Hash` in keywords.cpp for the ASCII names keywords
+/// are limited to
+pub fn keyword_hash(name: &str) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for &byte in name.as_bytes() {
+        hash = (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3);
+    }
+    hash
+}
+
+/// A keyword in the compiler's in-memory registry
+#[derive(Clone, Debug, PartialEq)]
+pub struct RegisteredKeyword {
+    pub hash: u64,
+    pub name: String,
+}
+
+#[repr(C)]
+pub(crate) struct RawKeyword {
+    keyword: *const c_char,
+    hash: u64,
+}
+
+pub(crate) unsafe fn collect(raw: *const RawKeyword, count: usize) -> Vec<RegisteredKeyword> {
+    crate::reflect::slice(raw, count)
+        .iter()
+        .map(|k| RegisteredKeyword {
+            hash: k.hash,
+            name: crate::reflect::name(k.keyword),
+        })
+        .collect()
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct KeywordRecord {
+    pub name: String,
+    /// Shader the keyword was first registered from
+    pub shader: String,
+}
+
+/// Every keyword the project has used, kept on disk so a new keyword is
+/// checked for hash collisions against all earlier builds, not just the
+/// current process.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct KeywordRegistry {
+    keywords: BTreeMap<u64, KeywordRecord>,
+}
+
+impl KeywordRegistry {
+    pub fn new() -> Self {
+        KeywordRegistry::default()
+    }
+
+    pub fn get(&self, hash: u64) -> Option<&KeywordRecord> {
+        self.keywords.get(&hash)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (u64, &KeywordRecord)> {
+        self.keywords.iter().map(|(&hash, record)| (hash, record))
+    }
+
+    pub fn len(&self) -> usize {
+        self.keywords.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keywords.is_empty()
+    }
+
+    /// Fails if a different keyword already has this one's hash
+    pub fn check(&self, name: &str) -> Result<u64, RegistryError> {
+        let hash = keyword_hash(name);
+        match self.keywords.get(&hash) {
+            Some(record) if record.name != name => Err(RegistryError::Collision {
+                hash,
+                existing: record.clone(),
+                name: name.to_string(),
+            }),
+            _ => Ok(hash),
+        }
+    }
+
+    /// Adds the keyword unless it's already known, returning whether it was
+    /// new
+    pub fn register(&mut self, name: &str, shader: &str) -> Result<bool, RegistryError> {
+        let hash = self.check(name)?;
+        if self.keywords.contains_key(&hash) {
+            return Ok(false);
+        }
+        self.keywords.insert(
+            hash,
+            KeywordRecord {
+                name: name.to_string(),
+                shader: shader.to_string(),
+            },
+        );
+        Ok(true)
+    }
+
+    /// Checks a build's keywords against the project's history here rather
+    /// than reserving the history with the compiler, whose registry only has
+    /// room for the keywords one build uses
+    pub fn check_all<S: AsRef<str>>(&self, names: &[S]) -> Result<(), RegistryError> {
+        for name in names {
+            self.check(name.as_ref())?;
+        }
+        Ok(())
+    }
+
+    pub fn from_json(text: &str) -> Result<Self, RegistryError> {
+        // Hashes are hex strings on disk, JSON keys can't be numbers
+        let records: BTreeMap<String, KeywordRecord> =
+            serde_json::from_str(text).map_err(RegistryError::Json)?;
+        let mut registry = KeywordRegistry::new();
+        for (hash, record) in records {
+            let value = u64::from_str_radix(hash.trim_start_matches("0x"), 16)
+                .map_err(|_| RegistryError::BadHash(hash.clone()))?;
+            if value != keyword_hash(&record.name) {
+                return Err(RegistryError::BadHash(hash));
+            }
+            registry.keywords.insert(value, record);
+        }
+        Ok(registry)
+    }
+
+    pub fn to_json(&self) -> String {
+        let records: BTreeMap<String, &KeywordRecord> = self
+            .keywords
+            .iter()
+            .map(|(hash, record)| (format!("0x{:016x}", hash), record))
+            .collect();
+        serde_json::to_string_pretty(&records).unwrap()
+    }
+
+    /// A missing file is an empty registry, the first build creates it
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, RegistryError> {
+        match fs::read_to_string(path) {
+            Ok(text) => KeywordRegistry::from_json(&text),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(KeywordRegistry::new()),
+            Err(e) => Err(RegistryError::Io(e)),
+        }
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), RegistryError> {
+        fs::write(path, self.to_json()).map_err(RegistryError::Io)
+    }
+}
+
+#[derive(Debug)]
+pub enum RegistryError {
+    Io(io::Error),
+    Json(serde_json::Error),
+    /// A hash that isn't hex or doesn't match its keyword
+    BadHash(String),
+    Collision {
+        hash: u64,
+        existing: KeywordRecord,
+        name: String,
+    },
+}
+
+impl fmt::Display for RegistryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RegistryError::Io(e) => write!(f, "cannot access keyword registry: {}", e),
+            RegistryError::Json(e) => write!(f, "invalid keyword registry: {}", e),
+            RegistryError::BadHash(hash) => {
+                write!(f, "keyword registry hash '{}' is invalid", hash)
+            }
+            RegistryError::Collision {
+                hash,
+                existing,
+                name,
+            } => write!(
+                f,
+                "'{}' collides with hash 0x{:016x} for '{}' from {}",
+                name, hash, existing.name, existing.shader
+            ),
+        }
+    }
+}
+
+impl std::error::Error for RegistryError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hash_is_fnv1a_64() {
+        assert_eq!(keyword_hash(""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(keyword_hash("a"), 0xaf63_dc4c_8601_ec8c);
+    }
+
+    #[test]
+    fn registers_each_keyword_once() {
+        let mut registry = KeywordRegistry::new();
+        assert!(registry.register("LIT", "water.frag").unwrap());
+        assert!(!registry.register("LIT", "sky.frag").unwrap());
+        assert_eq!(registry.len(), 1);
+        assert_eq!(
+            registry.get(keyword_hash("LIT")).unwrap().shader,
+            "water.frag"
+        );
+        assert!(registry.check_all(&["LIT", "FOG"]).is_ok());
+    }
+
+    #[test]
+    fn rejects_a_different_keyword_with_a_known_hash() {
+        // No two short keywords collide, so plant a record under FOG's hash
+        let mut registry = KeywordRegistry::new();
+        registry.keywords.insert(
+            keyword_hash("FOG"),
+            KeywordRecord {
+                name: "MIST".to_string(),
+                shader: "water.frag".to_string(),
+            },
+        );
+        assert!(matches!(
+            registry.register("FOG", "sky.frag"),
+            Err(RegistryError::Collision { ref name, .. }) if name == "FOG"
+        ));
+        assert!(registry.check_all(&["LIT", "FOG"]).is_err());
+        assert_eq!(registry.len(), 1);
+    }
+
+    #[test]
+    fn round_trips_through_json() {
+        let mut registry = KeywordRegistry::new();
+        registry.register("LIT", "water.frag").unwrap();
+        registry.register("QUALITY=2", "sky.frag").unwrap();
+        let json = registry.to_json();
+        assert!(json.contains(&format!("0x{:016x}", keyword_hash("LIT"))));
+        assert_eq!(KeywordRegistry::from_json(&json).unwrap(), registry);
+    }
+
+    #[test]
+    fn rejects_hashes_that_dont_match_their_keyword() {
+        let record = r#"{"name": "LIT", "shader": "water.frag"}"#;
+        let wrong = format!(r#"{{"0x{:016x}": {}}}"#, keyword_hash("FOG"), record);
+        assert!(matches!(
+            KeywordRegistry::from_json(&wrong),
+            Err(RegistryError::BadHash(_))
+        ));
+        let not_hex = format!(r#"{{"LIT": {}}}"#, record);
+        assert!(matches!(
+            KeywordRegistry::from_json(&not_hex),
+            Err(RegistryError::BadHash(_))
+        ));
+    }
+
+    #[test]
+    fn only_a_missing_file_loads_empty() {
+        let path = std::env::temp_dir().join(format!("keywords-{}.json", std::process::id()));
+        assert!(KeywordRegistry::load(&path).unwrap().is_empty());
+
+        fs::write(&path, "{\"0x").unwrap();
+        let corrupt = KeywordRegistry::load(&path);
+        fs::remove_file(&path).unwrap();
+        assert!(matches!(corrupt, Err(RegistryError::Json(_))));
+    }
+}
@@ -0,0 +1,157 @@
+use crate::mask::KeywordMask;
+use crate::ShaderCompiler;
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+
+/// Turns keywords on until dropped, then turns off the ones it turned on.
+/// Keywords that were already enabled stay enabled, so scopes nest in any
+/// order. Derefs to the compiler so scopes can be opened through each other.
+pub(crate) struct KeywordScope<'a> {
+    compiler: &'a mut ShaderCompiler,
+    added: KeywordMask,
+}
+
+impl<'a> KeywordScope<'a> {
+    pub(crate) fn new(compiler: &'a mut ShaderCompiler, mask: &KeywordMask) -> Self {
+        let added = mask.difference(&compiler.enable_keywords(mask));
+        KeywordScope { compiler, added }
+    }
+
+    /// Keywords this scope enabled and will disable
+    pub fn added(&self) -> &KeywordMask {
+        &self.added
+    }
+}
+
+impl Deref for KeywordScope<'_> {
+    type Target = ShaderCompiler;
+
+    fn deref(&self) -> &ShaderCompiler {
+        self.compiler
+    }
+}
+
+impl DerefMut for KeywordScope<'_> {
+    fn deref_mut(&mut self) -> &mut ShaderCompiler {
+        self.compiler
+    }
+}
+
+impl Drop for KeywordScope<'_> {
+    fn drop(&mut self) {
+        self.compiler.disable_keywords(&self.added);
+    }
+}
+
+/// `KeywordScope` for the keywords enabled on every thread. Only the keywords
+/// it turned on are turned off again, but another thread that enabled one of
+/// them meanwhile loses it too.
+pub struct GlobalKeywordScope {
+    added: KeywordMask,
+}
+
+impl GlobalKeywordScope {
+    pub(crate) fn new(mask: &KeywordMask) -> Self {
+        let added = mask.difference(&ShaderCompiler::enable_global_keywords(mask));
+        GlobalKeywordScope { added }
+    }
+
+    pub fn added(&self) -> &KeywordMask {
+        &self.added
+    }
+}
+
+impl Drop for GlobalKeywordScope {
+    fn drop(&mut self) {
+        ShaderCompiler::disable_global_keywords(&self.added);
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ToggleError {
+    /// The keyword was never added or reserved
+    Unregistered(String),
+    /// Disabling a keyword that isn't enabled
+    NotEnabled(String),
+    /// The compiler wouldn't take the keyword, its hash collides with another
+    /// keyword's, the registry is full or the program is already compiled
+    Rejected(String),
+}
+
+impl fmt::Display for ToggleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ToggleError::Unregistered(kw) => write!(f, "keyword '{}' isn't registered", kw),
+            ToggleError::NotEnabled(kw) => write!(f, "keyword '{}' isn't enabled", kw),
+            ToggleError::Rejected(kw) => write!(f, "keyword '{}' was rejected", kw),
+        }
+    }
+}
+
+impl std::error::Error for ToggleError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::limits::ResourceLimits;
+
+    #[test]
+    fn toggle_errors_name_the_keyword() {
+        let errors = [
+            ToggleError::Unregistered("FOG".to_string()),
+            ToggleError::NotEnabled("FOG".to_string()),
+            ToggleError::Rejected("FOG".to_string()),
+        ];
+        let messages: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+        assert_eq!(
+            messages,
+            [
+                "keyword 'FOG' isn't registered",
+                "keyword 'FOG' isn't enabled",
+                "keyword 'FOG' was rejected",
+            ]
+        );
+    }
+
+    #[test]
+    fn scopes_only_clear_the_keywords_they_set() {
+        let mut compiler = ShaderCompiler::new(&ResourceLimits::default());
+        ShaderCompiler::reserve_keyword("SCOPE_OUTER").unwrap();
+        ShaderCompiler::reserve_keyword("SCOPE_INNER").unwrap();
+        let outer = ShaderCompiler::keyword_bit("SCOPE_OUTER").unwrap();
+        let inner = ShaderCompiler::keyword_bit("SCOPE_INNER").unwrap();
+        compiler.enable_keyword("SCOPE_OUTER").unwrap();
+        {
+            let mut scope = compiler
+                .keyword_scope(&["SCOPE_OUTER", "SCOPE_INNER"])
+                .unwrap();
+            assert!(!scope.added().contains(outer));
+            assert!(scope.added().contains(inner));
+            {
+                // Nothing left for a nested scope to add, or to take away
+                let nested = scope.keyword_scope(&["SCOPE_INNER"]).unwrap();
+                assert!(nested.added().is_empty());
+            }
+            assert!(scope.enabled_keywords().contains(inner));
+        }
+        let enabled = compiler.enabled_keywords();
+        assert!(enabled.contains(outer));
+        assert!(!enabled.contains(inner));
+    }
+
+    #[test]
+    fn toggles_report_their_errors() {
+        let mut compiler = ShaderCompiler::new(&ResourceLimits::default());
+        assert_eq!(
+            compiler.keyword_scope(&["SCOPE_NEVER_REGISTERED"]).err(),
+            Some(ToggleError::Unregistered(
+                "SCOPE_NEVER_REGISTERED".to_string()
+            ))
+        );
+        ShaderCompiler::reserve_keyword("SCOPE_IDLE").unwrap();
+        assert_eq!(
+            compiler.disable_keyword("SCOPE_IDLE"),
+            Err(ToggleError::NotEnabled("SCOPE_IDLE".to_string()))
+        );
+    }
+}
@@ -0,0 +1,143 @@
+use crate::builder::VariantResult;
+use crate::variant::VariantKey;
+
+/// The variant picked for an enabled keyword set
+#[derive(Clone, Debug, PartialEq)]
+pub struct Selection {
+    /// Position in the selector's variant list
+    pub index: usize,
+    pub key: VariantKey,
+    pub exact: bool,
+    /// Enabled keywords the picked variant doesn't have
+    pub unsatisfied: Vec<String>,
+}
+
+/// Picks the compiled variant to draw with at runtime. The enabled set is the
+/// global keywords plus the material's own.
+///
+/// An exact match wins. Otherwise the variant that has the most of the enabled
+/// keywords and none that aren't enabled is used, the earliest in the list on
+/// a tie, so a variant never turns on a feature that was asked to be off.
+#[derive(Clone, Debug, Default)]
+pub struct VariantSelector {
+    variants: Vec<(VariantKey, Vec<String>)>,
+}
+
+impl VariantSelector {
+    pub fn new() -> Self {
+        VariantSelector::default()
+    }
+
+    pub fn add<S: AsRef<str>>(&mut self, keywords: &[S]) -> &mut Self {
+        let keywords = VariantKey::canonical(keywords);
+        self.variants.push((VariantKey::new(&keywords), keywords));
+        self
+    }
+
+    /// The linked variants of a build, in build order. Their keys match the
+    /// build's, enabled keywords included.
+    pub fn from_results(results: &[VariantResult]) -> Self {
+        let mut selector = VariantSelector::new();
+        for result in results.iter().filter(|r| r.linked) {
+            selector.add(&result.all_keywords);
+        }
+        selector
+    }
+
+    pub fn len(&self) -> usize {
+        self.variants.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.variants.is_empty()
+    }
+
+    /// None when every variant has a keyword that isn't enabled
+    pub fn select<S: AsRef<str>>(&self, enabled: &[S]) -> Option<Selection> {
+        let enabled = VariantKey::canonical(enabled);
+        if let Some(index) = self.variants.iter().position(|(_, k)| *k == enabled) {
+            return Some(Selection {
+                index,
+                key: self.variants[index].0,
+                exact: true,
+                unsatisfied: Vec::new(),
+            });
+        }
+
+        let mut best: Option<usize> = None;
+        for (index, (_, keywords)) in self.variants.iter().enumerate() {
+            if !keywords.iter().all(|k| enabled.contains(k)) {
+                continue;
+            }
+            match best {
+                Some(b) if self.variants[b].1.len() >= keywords.len() => {}
+                _ => best = Some(index),
+            }
+        }
+
+        best.map(|index| {
+            let (key, keywords) = &self.variants[index];
+            Selection {
+                index,
+                key: *key,
+                exact: false,
+                unsatisfied: enabled
+                    .iter()
+                    .filter(|k| !keywords.contains(k))
+                    .cloned()
+                    .collect(),
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn selector() -> VariantSelector {
+        let mut selector = VariantSelector::new();
+        selector
+            .add::<&str>(&[])
+            .add(&["LIT"])
+            .add(&["FOG"])
+            .add(&["LIT", "FOG"])
+            .add(&["SHADOWS"]);
+        selector
+    }
+
+    #[test]
+    fn exact_match_wins_in_any_order() {
+        let selection = selector().select(&["FOG", "LIT"]).unwrap();
+        assert_eq!(selection.index, 3);
+        assert!(selection.exact);
+        assert_eq!(selection.key, VariantKey::new(&["LIT", "FOG"]));
+        assert!(selection.unsatisfied.is_empty());
+    }
+
+    #[test]
+    fn falls_back_to_the_largest_subset() {
+        let selection = selector().select(&["LIT", "FOG", "RAIN"]).unwrap();
+        assert_eq!(selection.index, 3);
+        assert!(!selection.exact);
+        assert_eq!(selection.unsatisfied, ["RAIN"]);
+
+        let selection = selector().select(&["RAIN"]).unwrap();
+        assert_eq!(selection.index, 0);
+    }
+
+    #[test]
+    fn earliest_variant_wins_a_tie() {
+        let selection = selector().select(&["LIT", "SHADOWS", "RAIN"]).unwrap();
+        assert_eq!(selection.index, 1);
+        assert_eq!(selection.unsatisfied, ["RAIN", "SHADOWS"]);
+    }
+
+    #[test]
+    fn never_picks_a_variant_with_keywords_that_are_off() {
+        let mut selector = VariantSelector::new();
+        selector.add(&["LIT"]).add(&["LIT", "FOG"]);
+        assert_eq!(selector.select(&["FOG"]), None);
+        assert_eq!(VariantSelector::new().select(&["LIT"]), None);
+    }
+}
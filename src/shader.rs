@@ -1,3 +1,5 @@
+use crate::keywords::KeywordSpace;
+use crate::lint::KeywordUsage;
 use std::fs;
 use std::str::Lines;
 
@@ -30,6 +32,8 @@ pub struct Parser {
     pass_start: Vec<usize>,
     pass_end: Vec<usize>,
     pass_stages: PassStages,
+    keywords: KeywordSpace,
+    usage: KeywordUsage,
 }
 
 impl Parser {
@@ -42,8 +46,12 @@ impl Parser {
             pass_start: Vec::new(),
             pass_end: Vec::new(),
             pass_stages: PassStages::new(),
+            keywords: KeywordSpace::new(),
+            usage: KeywordUsage::default(),
         };
         p.get_name();
+        p.get_variants();
+        p.check_keywords();
         p.get_passes();
         p
     }
@@ -66,6 +74,75 @@ impl Parser {
         &self.name
     }
 
+    // `variants [name:] keywords...` lines, in the header or inside a pass,
+    // each add an axis to the keyword space. `constraint` lines are read once
+    // every axis is known
+    pub fn get_variants(&mut self) -> &KeywordSpace {
+        self.keywords = KeywordSpace::new();
+        if let Some(shader) = &self.shader {
+            for line in shader.lines() {
+                if let Some(statement) = Parser::statement(line, "variants") {
+                    if let Err(e) = self.keywords.parse_axis(statement) {
+                        println!(
+                            "{}: invalid variants statement '{}': {}",
+                            self.file,
+                            line.trim(),
+                            e
+                        );
+                    }
+                }
+            }
+            for line in shader.lines() {
+                if let Some(statement) = Parser::statement(line, "constraint") {
+                    if let Err(e) = self.keywords.parse_constraint(statement) {
+                        println!("{}: invalid constraint '{}': {}", self.file, line.trim(), e);
+                    }
+                }
+            }
+        }
+        &self.keywords
+    }
+
+    // The rest of a line that starts with `keyword`, matched case-insensitively
+    fn statement<'a>(line: &'a str, keyword: &str) -> Option<&'a str> {
+        let line = line.trim();
+        let head = line.get(..keyword.len())?;
+        let rest = &line[keyword.len()..];
+        if head.eq_ignore_ascii_case(keyword) && rest.starts_with(char::is_whitespace) {
+            Some(rest)
+        } else {
+            None
+        }
+    }
+
+    /// The `name = "..."` of the shader header
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    pub fn pass_names(&self) -> &[String] {
+        &self.pass_names
+    }
+
+    pub fn keyword_space(&self) -> &KeywordSpace {
+        &self.keywords
+    }
+
+    // Compares the declared keywords with the macros the stage code tests
+    pub fn check_keywords(&mut self) -> &KeywordUsage {
+        if let Some(shader) = &self.shader {
+            self.usage = KeywordUsage::check(&self.keywords, [shader.as_str()]);
+            for warning in self.usage.warnings() {
+                println!("{}: {}", self.file, warning);
+            }
+        }
+        &self.usage
+    }
+
+    pub fn keyword_usage(&self) -> &KeywordUsage {
+        &self.usage
+    }
+
     pub fn get_passes(&mut self) {
         if let Some(shader) = &self.shader {
             let mut count = 0;
@@ -151,10 +228,6 @@ impl Parser {
                             }
                         }
 
-                        if upper.starts_with("VARIANTS") {
-                            println!("Found Variant Statement!");
-                        }
-
                         if let Some(s) = end_stage {
                             stages[s as usize].2 = count - 1;
                             end_stage = None;
@@ -189,3 +262,18 @@ impl Parser {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn collected_variants_are_looked_up_by_parsed_names() {
+        let shader = Parser::new("src/shaders/deferredwithtransparent.esl");
+        assert_eq!(shader.name(), Some("Deferred with Forward Transparency"));
+        assert_eq!(
+            shader.pass_names(),
+            ["GBuffer", "Composition", "Transparency"]
+        );
+    }
+}
@@ -5,36 +5,71 @@ Author: Richard Underhill 2020
 #include "spirv.h"
 
 #include <algorithm>
+#include <cstddef>
 #include <cstdio>
 #include <sstream>
 
-#include "DirStackFileIncluder.h"
 #include "book.h"
+#include "includer.h"
 #include "keywords.h"
 
 thread_local std::list<Shader> shaders;
 thread_local std::vector<unsigned int> spirv;
 thread_local KeywordCombo shaderHashes;
 thread_local std::string shaderComboResult;
+thread_local bool keywordsCollision;
 thread_local std::vector<KeywordCombo> threadKeywordCombos;
 thread_local std::vector<Keyword> threadKeywords;
-thread_local std::vector<Keyword> threadEnabledKeywords;
+thread_local KeywordMask threadEnabledKeywords{};
+// Bits this thread has looked up, so toggling keywords skips kw_mutex
+thread_local std::unordered_map<uint64_t, int> threadKeywordBits;
 thread_local uint64_t keywordsID;
 thread_local std::string source{};
+// The user preamble from SetPreamble, and the keyword defines of the program
+// being built, which Link clears
 thread_local std::string preamble{};
+thread_local std::string keywordPreamble{};
 thread_local std::string warningsErrors;
 thread_local std::string queryIDResult;
 thread_local std::unique_ptr<spvtools::SpirvTools> tools;
 thread_local std::unique_ptr<spvtools::Optimizer> optimizer;
 thread_local bool keywordAddEnable;
+thread_local std::vector<std::string> includeDirs;
+thread_local std::map<std::string, std::string> virtualIncludes;
+thread_local std::vector<const char*> includedFiles;
+
+// Captured SPIRV-Tools consumer output, with a C view for Rust
+struct Message {
+  int level;
+  spv_position_t position;
+  std::string text;
+};
+
+struct RawMessage {
+  int level;
+  std::size_t line;
+  std::size_t column;
+  std::size_t index;
+  const char* message;
+};
+
+thread_local std::vector<Message> messages;
+thread_local std::vector<RawMessage> rawMessages;
 
 std::vector<Keyword> keywords;
-std::vector<Keyword> enabledKeywords;
-std::atomic<bool> keywordsEnabled(false);
+// Index in keywords, which is the keyword's bit in a KeywordMask
+std::unordered_map<uint64_t, int> keywordBits;
+std::atomic<uint64_t> enabledKeywords[MaxKeywords / 64];
 std::mutex kw_mutex;
-std::mutex enabled_mutex;
 
-static TBuiltInResource Resources;
+thread_local TBuiltInResource Resources;
+
+// Initialise copies ResourceLimits from limits.rs over this, 92 ints then the
+// 9 bools of TLimits, so a glslang that adds fields must be matched there
+static_assert(sizeof(TBuiltInResource) == 92 * sizeof(int) + 12,
+              "TBuiltInResource no longer matches ResourceLimits");
+static_assert(offsetof(TBuiltInResource, limits) == 92 * sizeof(int),
+              "TBuiltInResource no longer matches ResourceLimits");
 
 const TBuiltInResource DefaultTBuiltInResource = {
     /* .MaxLights = */ 32,
@@ -180,20 +215,21 @@ enum TOptions {
   EOptionDumpBareVersion = (1 << 31),
 };
 
-extern "C" void Initialise() {
+// glslang has no TOptions bit for this, so it lives above the 32 used above
+const uint64_t EOptionNanMinMaxClamp = 1ull << 32;
+
+extern "C" void Initialise(const TBuiltInResource* resources) {
   printf("Initialising!\n");
   glslang::InitializeProcess();
   tools.reset(new spvtools::SpirvTools(SPV_ENV_UNIVERSAL_1_3));
   optimizer.reset(new spvtools::Optimizer(SPV_ENV_UNIVERSAL_1_3));
 
-  /*
-  auto print_msg_to_stderr = [](spv_message_level_t, const char*, const
-  spv_position&, const char* m) { std::cerr << "error: " << m << std::endl;
-  };
-  tools->SetMessageConsumer(print_msg_to_stderr);
-  optimizer->SetMessageConsumer(print_msg_to_stderr);
-  */
-  Resources = DefaultTBuiltInResource;
+  tools->SetMessageConsumer([](spv_message_level_t level, const char*,
+                                const spv_position_t& position,
+                                const char* m) {
+    messages.push_back(Message{level, position, std::string(m)});
+  });
+  Resources = resources ? *resources : DefaultTBuiltInResource;
   keywordAddEnable = true;
 }
 
@@ -201,24 +237,6 @@ extern "C" std::size_t GetSpirvSize() {
   return spirv.size();
 }
 
-extern "C" const char* DecompileToGLSL() {
-  spirv_cross::CompilerGLSL glsl(spirv);
-  source = glsl.compile();
-  return source.c_str();
-}
-
-extern "C" const char* DecompileToHLSL() {
-  spirv_cross::CompilerHLSL hlsl(spirv);
-  source = hlsl.compile();
-  return source.c_str();
-}
-
-extern "C" const char* DecompileToMetal() {
-  spirv_cross::CompilerMSL msl(spirv);
-  source = msl.compile();
-  return source.c_str();
-}
-
 extern "C" void* GetCompiledSource() {
   return (void*)source.data();
 }
@@ -231,22 +249,72 @@ extern "C" void DeleteProgram(void* program) {
   delete (glslang::TProgram*)program;
 }
 
-extern "C" std::size_t CompileShader(EShLanguage stage, const char* sourcecode) {
-  bool compile_failed = false;
-  printf("Compiling Shader Stage: %i\n", stage);
-  if (!shaderHashes.hash) {
-    if (threadEnabledKeywords.size()) {
-      for (auto& kw : threadEnabledKeywords) {
-        AddKeyword(kw.hash, kw.keyword);
-      }
+// Counts the vec4 slots used by the user (non built-in) varyings of a parsed
+// shader with the given storage qualifier
+static int CountVaryingVectors(glslang::TShader& shader,
+                               glslang::TStorageQualifier storage) {
+  int vectors = 0;
+  glslang::TIntermediate* intermediate = shader.getIntermediate();
+  if (!intermediate || !intermediate->getTreeRoot())
+    return vectors;
+
+  glslang::TIntermAggregate* root =
+      intermediate->getTreeRoot()->getAsAggregate();
+  if (!root)
+    return vectors;
+
+  for (auto* node : root->getSequence()) {
+    glslang::TIntermAggregate* objects = node->getAsAggregate();
+    if (!objects || objects->getOp() != glslang::EOpLinkerObjects)
+      continue;
+
+    for (auto* object : objects->getSequence()) {
+      glslang::TIntermSymbol* symbol = object->getAsSymbolNode();
+      if (!symbol)
+        continue;
+      const glslang::TType& type = symbol->getType();
+      if (type.getQualifier().storage != storage || type.isBuiltIn())
+        continue;
+      // Redeclared built-in blocks such as gl_PerVertex
+      if (type.getBasicType() == glslang::EbtBlock &&
+          type.getTypeName().compare(0, 3, "gl_") == 0)
+        continue;
+      vectors += glslang::TIntermediate::computeTypeLocationSize(
+          type, intermediate->getStage());
     }
+  }
+  return vectors;
+}
 
-    if (keywordsEnabled) {
-      std::lock_guard<std::mutex> guard(enabled_mutex);
-      for (auto& kw : enabledKeywords) {
-        AddKeyword(kw.hash, kw.keyword);
-      }
+// glslang doesn't enforce the varying limits for Vulkan input, so check the
+// ones our mobile targets trip over here rather than on the device
+static bool CheckResourceLimits(glslang::TShader& shader, EShLanguage stage) {
+  if (stage == EShLangVertex) {
+    int used = CountVaryingVectors(shader, glslang::EvqVaryingOut);
+    if (used > Resources.maxVertexOutputVectors) {
+      printf("Vertex shader uses %i output vectors, limit is %i\n", used,
+             Resources.maxVertexOutputVectors);
+      return false;
+    }
+  } else if (stage == EShLangFragment) {
+    int used = CountVaryingVectors(shader, glslang::EvqVaryingIn);
+    if (used > Resources.maxFragmentInputVectors) {
+      printf("Fragment shader uses %i input vectors, limit is %i\n", used,
+             Resources.maxFragmentInputVectors);
+      return false;
     }
+  }
+  return true;
+}
+
+extern "C" std::size_t CompileShader(EShLanguage stage,
+                                     const char* sourcecode,
+                                     uint64_t options) {
+  bool compile_failed = false;
+  printf("Compiling Shader Stage: %i\n", stage);
+  if (!shaderHashes.hash) {
+    keywordsCollision = false;
+    AddEnabledKeywords();
     if (shaderHashes.hashes.size()) {
       // Make sure the keywords are in a deteministic order for hash generation
       std::sort(shaderHashes.hashes.begin(), shaderHashes.hashes.end(),
@@ -264,12 +332,10 @@ extern "C" std::size_t CompileShader(EShLanguage stage, const char* sourcecode)
       }
       shaderHashes.hash = Hash(shaderComboResult);
 
-      // Authenticate this combination
-      if (!AuthenticateKeywords()) {
-        printf(
-            "Panic! Compile Failed due to Keyword Combination Collision!!!!\n");
-        return 0;
-      }
+      // Authenticate this combination, a collision doesn't stop the compile
+      // as the keyword defines are still right, only the ID is ambiguous
+      if (!AuthenticateKeywords())
+        keywordsCollision = true;
       // std::cout << std::endl;
     } else {
       keywordsID = 0;
@@ -277,25 +343,37 @@ extern "C" std::size_t CompileShader(EShLanguage stage, const char* sourcecode)
   }
 
   keywordsID = shaderHashes.hash;
-  shaders.emplace_back(sourcecode, stage);
+  shaders.emplace_back(sourcecode, stage, options);
   glslang::TShader& shader = shaders.back().shader;
   shader.setStrings(&sourcecode, 1);
   shader.setEnvInput(glslang::EShSourceGlsl, stage, glslang::EShClientVulkan,
                      100);
   shader.setEnvClient(glslang::EShClientVulkan, glslang::EShTargetVulkan_1_0);
   shader.setEnvTarget(glslang::EShTargetSpv, glslang::EShTargetSpv_1_0);
-  shader.setNanMinMaxClamp(false);
-  shader.setInvertY(true);
-  shader.setAutoMapBindings(true);
-  shader.setAutoMapLocations(true);
-  // printf("Preamble:\n%s", preamble.c_str());
-  if (!preamble.empty()) {
-    shader.setPreamble(preamble.c_str());
-  }
-
-  DirStackFileIncluder includer;
-
-  if (!shader.parse(&Resources, 100, false, EShMsgDefault, includer))
+  shader.setNanMinMaxClamp((options & EOptionNanMinMaxClamp) != 0);
+  shader.setInvertY((options & EOptionInvertY) != 0);
+  shader.setAutoMapBindings((options & EOptionAutoMapBindings) != 0);
+  shader.setAutoMapLocations((options & EOptionAutoMapLocations) != 0);
+  shader.setHlslIoMapping((options & EOptionHlslIoMapping) != 0);
+
+  EShMessages messages = EShMsgDefault;
+  if (options & EOptionRelaxedErrors)
+    messages = (EShMessages)(messages | EShMsgRelaxedErrors);
+  if (options & EOptionHlslOffsets)
+    messages = (EShMessages)(messages | EShMsgHlslOffsets);
+  const int defaultVersion = (options & EOptionDefaultDesktop) ? 110 : 100;
+  // The user preamble comes first so keywords can test platform defines
+  std::string fullPreamble =
+      "#extension GL_GOOGLE_include_directive : enable\n";
+  fullPreamble += preamble + keywordPreamble;
+  shader.setPreamble(fullPreamble.c_str());
+
+  ShaderIncluder includer(includeDirs, virtualIncludes,
+                          shaders.back().includes);
+
+  if (!shader.parse(&Resources, defaultVersion, false, messages, includer))
+    compile_failed = true;
+  else if (!CheckResourceLimits(shader, stage))
     compile_failed = true;
 
   if (compile_failed) {
@@ -313,6 +391,11 @@ extern "C" uint64_t GetKeywordsID() {
   return keywordsID;
 }
 
+// Whether the combination ID of the last compile is shared with another set
+extern "C" bool KeywordsCollided() {
+  return keywordsCollision;
+}
+
 Shader* GetShader(std::size_t handle) {
   if (handle) {
     // Get the address of this shader by walking the list
@@ -330,8 +413,8 @@ extern "C" std::size_t Recompile(std::size_t handle) {
   Shader* parent = GetShader(handle);
   std::size_t new_handle = 0;
   if (parent && parent->parent == nullptr) {
-    new_handle =
-        CompileShader(parent->shader.getStage(), parent->source.c_str());
+    new_handle = CompileShader(parent->shader.getStage(),
+                               parent->source.c_str(), parent->options);
     shaders.back().parent = parent;
   }
   return new_handle;
@@ -344,6 +427,27 @@ extern "C" void Add(glslang::TProgram* program, std::size_t handle) {
   }
 }
 
+extern "C" void AddIncludeDir(const char* dir) {
+  includeDirs.push_back(dir);
+}
+
+// Shadows any file of the same name in the include directories
+extern "C" void AddVirtualInclude(const char* name, const char* contents) {
+  virtualIncludes[name] = contents;
+}
+
+extern "C" const char* const* GetIncludedFiles(std::size_t handle,
+                                               std::size_t* count) {
+  includedFiles.clear();
+  Shader* shader = GetShader(handle);
+  if (shader) {
+    for (auto& name : shader->includes)
+      includedFiles.push_back(name.c_str());
+  }
+  *count = includedFiles.size();
+  return includedFiles.data();
+}
+
 extern "C" void SetPreamble(const char* sourcecode) {
   preamble = std::string(sourcecode);
 }
@@ -366,16 +470,16 @@ extern "C" bool Link(glslang::TProgram* program) {
   }
   shaderHashes.hash = 0;
   shaderHashes.hashes.clear();
-  preamble.clear();
+  keywordPreamble.clear();
   keywordAddEnable = true;
   return result;
 }
 
-extern "C" void PrintSpirv() {
-  printf("SPIRV size: %zu\n", spirv.size());
+extern "C" void PrintSpirv(unsigned int* ptr, std::size_t length) {
+  printf("SPIRV size: %zu\n", length);
   std::size_t count = 0;
-  for (auto& entry : spirv) {
-    printf("%.8x ", entry);
+  for (std::size_t i = 0; i < length; ++i) {
+    printf("%.8x ", ptr[i]);
     if (!(++count & 7)) {
       printf("\n");
     }
@@ -389,37 +493,95 @@ extern "C" void CopyToSpirv(unsigned int* ptr, std::size_t length) {
 }
 
 extern "C" void* GetSpirvForStage(glslang::TProgram* program,
-                                  EShLanguage stage) {
+                                  EShLanguage stage,
+                                  uint64_t options) {
   unsigned int* sp = nullptr;
   if (program->getIntermediate(stage)) {
     spirv.clear();
     spv::SpvBuildLogger logger;
     glslang::SpvOptions spvOptions;
-    spvOptions.generateDebugInfo = true;
+    spvOptions.generateDebugInfo = (options & EOptionDebug) != 0;
     spvOptions.validate = true;
     spvOptions.disassemble = true;
     glslang::GlslangToSpv(*program->getIntermediate(stage), spirv, &logger,
                           &spvOptions);
     printf("Stage %i SPIRV\n", stage);
     printf("%s", logger.getAllMessages().c_str());
-    printf("%s", preamble.c_str());
     // PrintSpirv();
     sp = spirv.data();
   }
   return sp;
 }
 
+extern "C" const unsigned int* GetSpirv() {
+  return spirv.data();
+}
+
 extern "C" bool Assemble(const char* code) {
+  messages.clear();
   source = std::string(code);
+  spirv.clear();
   return tools->Assemble(source, &spirv);
 }
 
 extern "C" bool Validate(unsigned int* ptr, std::size_t length) {
+  messages.clear();
   CopyToSpirv(ptr, length);
   return tools->Validate(spirv);
 }
 
-extern "C" void Optimize() {}
+// Messages from the last Assemble or Validate call
+extern "C" const RawMessage* GetMessages(std::size_t* count) {
+  rawMessages.clear();
+  for (auto& m : messages) {
+    rawMessages.push_back(RawMessage{m.level, m.position.line,
+                                     m.position.column, m.position.index,
+                                     m.text.c_str()});
+  }
+  *count = rawMessages.size();
+  return rawMessages.data();
+}
+
+enum OptLevel {
+  EOptLevelNone = 0,
+  EOptLevelPerformance = 1,
+  EOptLevelSize = 2,
+  EOptLevelCustom = 3,
+};
+
+// Optimises the module into the thread's spirv buffer. Custom passes are
+// spirv-opt command line flags, run in order after any preset.
+extern "C" const unsigned int* Optimize(unsigned int* ptr,
+                                        std::size_t length,
+                                        int level,
+                                        const char* const* passes,
+                                        std::size_t count) {
+  CopyToSpirv(ptr, length);
+  optimizer.reset(new spvtools::Optimizer(SPV_ENV_UNIVERSAL_1_3));
+
+  if (level == EOptLevelPerformance || level == EOptLevelSize) {
+    optimizer->RegisterPass(spvtools::CreateStripDebugInfoPass());
+  }
+  if (level == EOptLevelPerformance) {
+    optimizer->RegisterPerformancePasses();
+  } else if (level == EOptLevelSize) {
+    optimizer->RegisterSizePasses();
+  }
+
+  std::vector<std::string> flags(passes, passes + count);
+  if (!flags.empty() && !optimizer->RegisterPassesFromFlags(flags)) {
+    printf("Optimize Failed! Unknown pass in custom list\n");
+    return nullptr;
+  }
+
+  std::vector<unsigned int> optimized;
+  if (!optimizer->Run(spirv.data(), spirv.size(), &optimized)) {
+    printf("Optimize Failed!\n");
+    return nullptr;
+  }
+  spirv.swap(optimized);
+  return spirv.data();
+}
 
 extern "C" const char* Disassemble(unsigned int* ptr, std::size_t length) {
   CopyToSpirv(ptr, length);
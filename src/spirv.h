@@ -1,4 +1,5 @@
 #pragma once
+#include <glslang/MachineIndependent/localintermediate.h>
 #include <glslang/SPIRV/GlslangToSpv.h>
 
 #include <atomic>
@@ -8,16 +9,24 @@
 #include <spirv_cross/spirv_glsl.hpp>
 #include <spirv_cross/spirv_hlsl.hpp>
 #include <spirv_cross/spirv_msl.hpp>
+#include <spirv_cross/spirv_reflect.hpp>
+#include <unordered_map>
 #include <vector>
 
+#include "keywords.h"
+
 struct Shader {
-  Shader(const char *src, EShLanguage stage, Shader *pshader = nullptr)
+  Shader(const char *src, EShLanguage stage, uint64_t opts,
+         Shader *pshader = nullptr)
       : source(std::string(src)), shader(glslang::TShader(stage)),
-        parent(pshader) {}
+        options(opts), parent(pshader) {}
 
   std::string source;
   glslang::TShader shader;
+  uint64_t options;
   Shader *parent;
+  // Files pulled in by #include, in the order they were first included
+  std::vector<std::string> includes;
 };
 
 struct Keyword {
@@ -33,15 +42,16 @@ struct KeywordCombo {
 extern thread_local std::vector<unsigned int> spirv;
 extern thread_local KeywordCombo shaderHashes;
 extern thread_local std::string preamble;
+extern thread_local std::string keywordPreamble;
 extern thread_local std::vector<KeywordCombo> threadKeywordCombos;
 extern thread_local std::vector<Keyword> threadKeywords;
-extern thread_local std::vector<Keyword> threadEnabledKeywords;
+extern thread_local KeywordMask threadEnabledKeywords;
+extern thread_local std::unordered_map<uint64_t, int> threadKeywordBits;
 extern thread_local std::string queryIDResult;
 extern thread_local uint64_t keywordsID;
 extern thread_local bool keywordAddEnable;
 
 extern std::vector<Keyword> keywords;
-extern std::vector<Keyword> enabledKeywords;
-extern std::atomic<bool> keywordsEnabled;
+extern std::unordered_map<uint64_t, int> keywordBits;
+extern std::atomic<uint64_t> enabledKeywords[MaxKeywords / 64];
 extern std::mutex kw_mutex;
-extern std::mutex enabled_mutex;
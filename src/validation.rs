@@ -0,0 +1,160 @@
+use std::ffi::CStr;
+use std::fmt;
+use std::os::raw::c_char;
+
+// Layout of RawMessage in spirv.cpp
+#[repr(C)]
+pub(crate) struct RawMessage {
+    level: i32,
+    line: usize,
+    column: usize,
+    index: usize,
+    message: *const c_char,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum MessageLevel {
+    Fatal,
+    InternalError,
+    Error,
+    Warning,
+    Info,
+    Debug,
+}
+
+impl MessageLevel {
+    // spv_message_level_t values
+    fn from_raw(level: i32) -> Self {
+        match level {
+            0 => MessageLevel::Fatal,
+            1 => MessageLevel::InternalError,
+            2 => MessageLevel::Error,
+            3 => MessageLevel::Warning,
+            4 => MessageLevel::Info,
+            _ => MessageLevel::Debug,
+        }
+    }
+}
+
+/// A message from the SPIRV-Tools message consumer. `index` is the word
+/// offset into the module for validation, `line`/`column` are set when
+/// assembling text.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ValidationMessage {
+    pub level: MessageLevel,
+    pub line: usize,
+    pub column: usize,
+    pub index: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ValidationMessage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:?} at {}:{} (word {}): {}",
+            self.level, self.line, self.column, self.index, self.message
+        )
+    }
+}
+
+pub(crate) unsafe fn collect(raw: *const RawMessage, count: usize) -> Vec<ValidationMessage> {
+    if raw.is_null() {
+        return Vec::new();
+    }
+    std::slice::from_raw_parts(raw, count)
+        .iter()
+        .map(|m| ValidationMessage {
+            level: MessageLevel::from_raw(m.level),
+            line: m.line,
+            column: m.column,
+            index: m.index,
+            message: CStr::from_ptr(m.message).to_string_lossy().into_owned(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::limits::ResourceLimits;
+    use crate::module::SpirvModule;
+    use crate::ShaderCompiler;
+    use std::ffi::CString;
+
+    const FRAGMENT: &str = "OpCapability Shader
+OpMemoryModel Logical GLSL450
+OpEntryPoint Fragment %main \"main\"
+OpExecutionMode %main OriginUpperLeft
+%void = OpTypeVoid
+%fn = OpTypeFunction %void
+%main = OpFunction %void None %fn
+%entry = OpLabel
+OpReturn
+OpFunctionEnd
+";
+
+    #[test]
+    fn collects_raw_messages() {
+        let text = CString::new("bad id").unwrap();
+        let raw = [RawMessage {
+            level: 2,
+            line: 3,
+            column: 7,
+            index: 12,
+            message: text.as_ptr(),
+        }];
+        let messages = unsafe { collect(raw.as_ptr(), raw.len()) };
+        assert_eq!(
+            messages,
+            [ValidationMessage {
+                level: MessageLevel::Error,
+                line: 3,
+                column: 7,
+                index: 12,
+                message: "bad id".to_string(),
+            }]
+        );
+        assert_eq!(messages[0].to_string(), "Error at 3:7 (word 12): bad id");
+        assert!(unsafe { collect(std::ptr::null(), 4) }.is_empty());
+        assert_eq!(MessageLevel::from_raw(3), MessageLevel::Warning);
+        assert_eq!(MessageLevel::from_raw(9), MessageLevel::Debug);
+    }
+
+    #[test]
+    fn assembles_and_validates() {
+        let compiler = ShaderCompiler::new(&ResourceLimits::default());
+        let words = compiler.assemble(FRAGMENT).unwrap();
+        assert_eq!(compiler.validate(&SpirvModule::new(words).unwrap()), Ok(()));
+    }
+
+    #[test]
+    fn captures_assembler_errors_with_their_position() {
+        let compiler = ShaderCompiler::new(&ResourceLimits::default());
+        let messages = compiler
+            .assemble("OpCapability Shader\nOpNotAnInstruction\n")
+            .unwrap_err();
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].level, MessageLevel::Error);
+        assert_eq!(messages[0].line, 1);
+        assert!(!messages[0].message.is_empty());
+    }
+
+    #[test]
+    fn captures_validation_errors_per_call() {
+        let compiler = ShaderCompiler::new(&ResourceLimits::default());
+        // No entry point and no Linkage capability
+        let words = compiler
+            .assemble("OpCapability Shader\nOpMemoryModel Logical GLSL450\n")
+            .unwrap();
+        let messages = compiler
+            .validate(&SpirvModule::new(words).unwrap())
+            .unwrap_err();
+        assert!(!messages.is_empty());
+        assert!(messages.iter().all(|m| m.level == MessageLevel::Error));
+
+        // Messages from the failed run don't carry over
+        let words = compiler.assemble(FRAGMENT).unwrap();
+        assert_eq!(compiler.validate(&SpirvModule::new(words).unwrap()), Ok(()));
+    }
+}
@@ -0,0 +1,238 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+const FNV128_OFFSET: u128 = 0x6c62_272e_07bb_0142_62b8_2175_6295_c58d;
+const FNV128_PRIME: u128 = 0x0000_0000_0100_0000_0000_0000_0000_013b;
+
+/// Stable ID of a keyword set, a 128-bit FNV-1a hash of the sorted keyword
+/// names. It doesn't depend on the order keywords were enabled in, the
+/// thread, the run or the machine, so archives can be indexed by it.
+/// `VariantKeys` and `KeywordSpace::resolve` turn a key back into keywords.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct VariantKey(pub u128);
+
+impl VariantKey {
+    pub fn new<S: AsRef<str>>(keywords: &[S]) -> Self {
+        let mut hash = FNV128_OFFSET;
+        for keyword in VariantKey::canonical(keywords) {
+            // The zero byte keeps ["AB"] and ["A", "B"] apart
+            for &byte in keyword.as_bytes().iter().chain(&[0]) {
+                hash = (hash ^ byte as u128).wrapping_mul(FNV128_PRIME);
+            }
+        }
+        VariantKey(hash)
+    }
+
+    /// Sorted and deduplicated, the form the key is made from
+    pub fn canonical<S: AsRef<str>>(keywords: &[S]) -> Vec<String> {
+        let mut keywords: Vec<String> = keywords.iter().map(|k| k.as_ref().to_string()).collect();
+        keywords.sort();
+        keywords.dedup();
+        keywords
+    }
+
+    pub fn from_hex(hex: &str) -> Option<Self> {
+        u128::from_str_radix(hex.trim_start_matches("0x"), 16)
+            .ok()
+            .map(VariantKey)
+    }
+}
+
+impl fmt::Display for VariantKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:032x}", self.0)
+    }
+}
+
+/// Keyword sets of the keys a build made, saved next to the variants so a
+/// key read back from an archive resolves in any later run. Keys of a
+/// `KeywordSpace`'s own sets also resolve through `KeywordSpace::resolve`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct VariantKeys {
+    keys: BTreeMap<VariantKey, Vec<String>>,
+}
+
+impl VariantKeys {
+    pub fn new() -> Self {
+        VariantKeys::default()
+    }
+
+    /// Makes the key of the keyword set and records the set, failing if a
+    /// different set already has the key
+    pub fn insert<S: AsRef<str>>(&mut self, keywords: &[S]) -> Result<VariantKey, VariantKeyError> {
+        let keywords = VariantKey::canonical(keywords);
+        let key = VariantKey::new(&keywords);
+        match self.keys.get(&key) {
+            Some(existing) if *existing != keywords => Err(VariantKeyError::Collision {
+                key,
+                existing: existing.clone(),
+                keywords,
+            }),
+            Some(_) => Ok(key),
+            None => {
+                self.keys.insert(key, keywords);
+                Ok(key)
+            }
+        }
+    }
+
+    /// Sorted keywords of a recorded key
+    pub fn keywords(&self, key: VariantKey) -> Option<&[String]> {
+        self.keys.get(&key).map(|k| k.as_slice())
+    }
+
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (VariantKey, &[String])> {
+        self.keys.iter().map(|(&key, k)| (key, k.as_slice()))
+    }
+
+    pub fn from_json(text: &str) -> Result<Self, VariantKeyError> {
+        // Keys are hex strings on disk, JSON keys can't be numbers
+        let sets: BTreeMap<String, Vec<String>> =
+            serde_json::from_str(text).map_err(VariantKeyError::Json)?;
+        let mut keys = VariantKeys::new();
+        for (hex, keywords) in sets {
+            let key =
+                VariantKey::from_hex(&hex).ok_or_else(|| VariantKeyError::BadKey(hex.clone()))?;
+            if keys.insert(&keywords)? != key {
+                return Err(VariantKeyError::BadKey(hex));
+            }
+        }
+        Ok(keys)
+    }
+
+    pub fn to_json(&self) -> String {
+        let sets: BTreeMap<String, &Vec<String>> = self
+            .keys
+            .iter()
+            .map(|(key, keywords)| (key.to_string(), keywords))
+            .collect();
+        serde_json::to_string_pretty(&sets).unwrap()
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, VariantKeyError> {
+        let text = fs::read_to_string(path).map_err(VariantKeyError::Io)?;
+        VariantKeys::from_json(&text)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), VariantKeyError> {
+        fs::write(path, self.to_json()).map_err(VariantKeyError::Io)
+    }
+}
+
+#[derive(Debug)]
+pub enum VariantKeyError {
+    /// Two keyword sets hashed to the same key
+    Collision {
+        key: VariantKey,
+        existing: Vec<String>,
+        keywords: Vec<String>,
+    },
+    /// The compiler's 64-bit combination ID for this thread is already used by
+    /// another keyword set
+    Combination(u64),
+    Io(io::Error),
+    Json(serde_json::Error),
+    /// A key on disk that isn't hex or doesn't match its keywords
+    BadKey(String),
+}
+
+impl fmt::Display for VariantKeyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            VariantKeyError::Collision {
+                key,
+                existing,
+                keywords,
+            } => write!(
+                f,
+                "keywords [{}] collide with [{}] on variant key {}",
+                keywords.join(", "),
+                existing.join(", "),
+                key
+            ),
+            VariantKeyError::Combination(id) => {
+                write!(f, "keyword combination ID 0x{:016x} collides", id)
+            }
+            VariantKeyError::Io(e) => write!(f, "cannot access variant keys: {}", e),
+            VariantKeyError::Json(e) => write!(f, "invalid variant keys: {}", e),
+            VariantKeyError::BadKey(key) => write!(f, "variant key '{}' is invalid", key),
+        }
+    }
+}
+
+impl std::error::Error for VariantKeyError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keywords::KeywordSpace;
+
+    #[test]
+    fn key_is_fnv1a_of_the_canonical_set() {
+        assert_eq!(
+            VariantKey::new::<&str>(&[]).to_string(),
+            "6c62272e07bb014262b821756295c58d"
+        );
+        let key = VariantKey::new(&["LIT", "FOG"]);
+        assert_eq!(key.to_string(), "469f1cfef2659aa0935a266e6fd4d9f2");
+        assert_eq!(VariantKey::new(&["FOG", "LIT", "FOG"]), key);
+        assert_eq!(VariantKey::from_hex(&key.to_string()), Some(key));
+        assert_ne!(VariantKey::new(&["AB"]), VariantKey::new(&["A", "B"]));
+        assert_eq!(
+            VariantKey::canonical(&["LIT", "FOG", "LIT"]),
+            ["FOG", "LIT"]
+        );
+    }
+
+    #[test]
+    fn keys_round_trip_through_json() {
+        let mut keys = VariantKeys::new();
+        let lit = keys.insert(&["LIT"]).unwrap();
+        let both = keys.insert(&["LIT", "FOG"]).unwrap();
+        assert_eq!(keys.insert(&["FOG", "LIT"]).unwrap(), both);
+        assert_eq!(keys.len(), 2);
+
+        let read = VariantKeys::from_json(&keys.to_json()).unwrap();
+        assert_eq!(read, keys);
+        assert_eq!(read.keywords(lit), Some(&["LIT".to_string()][..]));
+        assert_eq!(read.keywords(VariantKey(0)), None);
+    }
+
+    #[test]
+    fn rejects_keys_that_dont_match_their_keywords() {
+        let json = format!(r#"{{"{}": ["FOG"]}}"#, VariantKey::new(&["LIT"]));
+        assert!(matches!(
+            VariantKeys::from_json(&json),
+            Err(VariantKeyError::BadKey(_))
+        ));
+        assert!(matches!(
+            VariantKeys::from_json(r#"{"LIT": ["LIT"]}"#),
+            Err(VariantKeyError::BadKey(_))
+        ));
+    }
+
+    #[test]
+    fn space_resolves_its_own_keys() {
+        let mut space = KeywordSpace::new();
+        space
+            .add_axis("lighting", &["_", "LIT", "UNLIT"])
+            .and_then(|s| s.add_axis("fog", &["_", "FOG"]))
+            .unwrap();
+        for indices in space.permutations() {
+            let key = VariantKey::new(&space.keywords(&indices).unwrap());
+            assert_eq!(space.resolve(key), Some(indices));
+        }
+        assert_eq!(space.resolve(VariantKey::new(&["SHADOWS"])), None);
+    }
+}